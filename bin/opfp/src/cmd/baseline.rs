@@ -0,0 +1,183 @@
+//! Comparison of program stats against a saved baseline.
+
+use clap::Args;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::path::PathBuf;
+
+use super::run_op_program::ProgramStats;
+
+/// CLI arguments for comparing program stats against a baseline.
+#[derive(Args, Clone, Debug, Default)]
+pub struct BaselineArgs {
+    /// Optional baseline stats file
+    #[clap(
+        long,
+        help = "Path to a stats file written by --output to compare the run against"
+    )]
+    pub baseline: Option<PathBuf>,
+    /// The relative increase over the baseline that counts as a regression
+    #[clap(
+        long,
+        default_value_t = 0.1,
+        help = "Relative increase over the baseline that fails the run (0.1 = 10%)"
+    )]
+    pub baseline_threshold: f64,
+}
+
+impl BaselineArgs {
+    /// Compares the stats against the configured baseline, printing the deltas.
+    ///
+    /// Returns an error if any metric regressed by more than the threshold.
+    pub fn check(&self, stats: &ProgramStats) -> Result<()> {
+        let Some(path) = &self.baseline else {
+            return Ok(());
+        };
+        let file = std::fs::File::open(path)
+            .map_err(|e| eyre!("Failed to open baseline {:?}: {}", path, e))?;
+        let baseline: ProgramStats = serde_json::from_reader(file)
+            .map_err(|e| eyre!("Failed to parse baseline {:?}: {}", path, e))?;
+
+        let deltas = compare(&baseline, stats);
+        print_deltas(&deltas);
+
+        let regressions: Vec<String> = deltas
+            .iter()
+            .filter(|delta| delta.regressed(self.baseline_threshold))
+            .map(|delta| format!("{} {:+.2}%", delta.metric, delta.change() * 100.0))
+            .collect();
+        if !regressions.is_empty() {
+            return Err(eyre!(
+                "Regressed by more than {}% over the baseline: {}",
+                self.baseline_threshold * 100.0,
+                regressions.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The change of a single metric relative to the baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricDelta {
+    /// The name of the metric.
+    pub metric: &'static str,
+    /// The baseline value.
+    pub baseline: u128,
+    /// The current value.
+    pub current: u128,
+}
+
+impl MetricDelta {
+    /// Returns the change relative to the baseline, e.g. `0.5` for a 50% increase.
+    pub fn change(&self) -> f64 {
+        if self.baseline == 0 {
+            return if self.current == 0 {
+                0.0
+            } else {
+                f64::INFINITY
+            };
+        }
+        (self.current as f64 - self.baseline as f64) / self.baseline as f64
+    }
+
+    /// Returns true if the metric increased by more than the relative threshold.
+    pub fn regressed(&self, threshold: f64) -> bool {
+        self.change() > threshold
+    }
+}
+
+/// Returns the deltas of every metric that is present in both the baseline and the current stats.
+pub fn compare(baseline: &ProgramStats, current: &ProgramStats) -> Vec<MetricDelta> {
+    let metric = |metric, baseline: Option<u64>, current: Option<u64>| {
+        Some(MetricDelta {
+            metric,
+            baseline: baseline?.into(),
+            current: current?.into(),
+        })
+    };
+
+    [
+        metric("instructions", baseline.instructions, current.instructions),
+        metric("pages", baseline.pages, current.pages),
+        metric("memory_used", baseline.memory_used, current.memory_used),
+        metric(
+            "num_preimage_requests",
+            baseline.num_preimage_requests,
+            current.num_preimage_requests,
+        ),
+        metric(
+            "total_preimage_size",
+            baseline.total_preimage_size,
+            current.total_preimage_size,
+        ),
+        Some(MetricDelta {
+            metric: "runtime",
+            baseline: baseline.runtime,
+            current: current.runtime,
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Prints a line per metric with its baseline value, current value and relative change.
+fn print_deltas(deltas: &[MetricDelta]) {
+    println!(
+        "{:<24} {:>16} {:>16} {:>10}",
+        "metric", "baseline", "current", "change"
+    );
+    for delta in deltas {
+        println!(
+            "{:<24} {:>16} {:>16} {:>9.2}%",
+            delta.metric,
+            delta.baseline,
+            delta.current,
+            delta.change() * 100.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let baseline = ProgramStats {
+            runtime: 1000,
+            instructions: Some(100),
+            pages: Some(10),
+            ..Default::default()
+        };
+        let current = ProgramStats {
+            runtime: 900,
+            instructions: Some(150),
+            memory_used: Some(4096),
+            ..Default::default()
+        };
+
+        let deltas = compare(&baseline, &current);
+        assert_eq!(
+            deltas.iter().map(|d| d.metric).collect::<Vec<_>>(),
+            vec!["instructions", "runtime"]
+        );
+        assert_eq!(deltas[0].change(), 0.5);
+        assert_eq!(deltas[1].change(), -0.1);
+        assert!(deltas[0].regressed(0.1));
+        assert!(!deltas[0].regressed(0.5));
+        assert!(!deltas[1].regressed(0.0));
+    }
+
+    #[test]
+    fn test_zero_baseline() {
+        let delta = |current| MetricDelta {
+            metric: "pages",
+            baseline: 0,
+            current,
+        };
+        assert_eq!(delta(0).change(), 0.0);
+        assert!(delta(1).regressed(10.0));
+    }
+}
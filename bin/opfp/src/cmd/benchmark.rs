@@ -0,0 +1,201 @@
+//! Statistics over repeated runs of a fixture.
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::Serialize;
+
+use super::run_op_program::ProgramStats;
+
+/// The results of running a fixture repeatedly.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Benchmark {
+    /// The number of runs.
+    pub runs: usize,
+    /// Statistics over the runtimes of the runs, in milliseconds.
+    pub runtime: RuntimeStats,
+}
+
+impl Benchmark {
+    /// Computes the benchmark of the given runs, checking that their deterministic metrics
+    /// match.
+    pub fn new(runs: &[ProgramStats]) -> Result<Self> {
+        check_determinism(runs)?;
+        let runtimes: Vec<u128> = runs.iter().map(|stats| stats.runtime).collect();
+        let runtime = RuntimeStats::new(&runtimes).ok_or(eyre!("No runs to benchmark"))?;
+
+        Ok(Self {
+            runs: runs.len(),
+            runtime,
+        })
+    }
+
+    /// Prints the runtime statistics.
+    pub fn print(&self) {
+        println!("Runtime over {} runs (ms)", self.runs);
+        println!("{:<8} {:>12}", "min", self.runtime.min);
+        println!("{:<8} {:>12.1}", "median", self.runtime.median);
+        println!("{:<8} {:>12.1}", "mean", self.runtime.mean);
+        println!("{:<8} {:>12}", "p95", self.runtime.p95);
+        println!("{:<8} {:>12}", "max", self.runtime.max);
+        println!("{:<8} {:>12.1}", "stddev", self.runtime.std_dev);
+    }
+}
+
+/// Summary statistics of a set of runtimes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeStats {
+    /// The fastest run.
+    pub min: u128,
+    /// The median, averaging the two middle runs for an even number of runs.
+    pub median: f64,
+    /// The arithmetic mean.
+    pub mean: f64,
+    /// The 95th percentile, using the nearest-rank method.
+    pub p95: u128,
+    /// The slowest run.
+    pub max: u128,
+    /// The sample standard deviation.
+    pub std_dev: f64,
+}
+
+impl RuntimeStats {
+    /// Computes the statistics of the given runtimes, or `None` if there are none.
+    pub fn new(runtimes: &[u128]) -> Option<Self> {
+        let mut sorted = runtimes.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let (min, max) = (*sorted.first()?, *sorted.last()?);
+
+        let median = if n.is_multiple_of(2) {
+            (sorted[n / 2 - 1] + sorted[n / 2]) as f64 / 2.0
+        } else {
+            sorted[n / 2] as f64
+        };
+        let mean = sorted.iter().sum::<u128>() as f64 / n as f64;
+        let p95 = sorted[(n * 95).div_ceil(100) - 1];
+        let std_dev = if n > 1 {
+            let variance = sorted
+                .iter()
+                .map(|&runtime| (runtime as f64 - mean).powi(2))
+                .sum::<f64>()
+                / (n - 1) as f64;
+            variance.sqrt()
+        } else {
+            0.0
+        };
+
+        Some(Self {
+            min,
+            median,
+            mean,
+            p95,
+            max,
+            std_dev,
+        })
+    }
+}
+
+/// Checks that every metric besides the runtime is identical across the runs.
+pub fn check_determinism(runs: &[ProgramStats]) -> Result<()> {
+    let Some(first) = runs.first() else {
+        return Ok(());
+    };
+
+    let mut mismatches = Vec::new();
+    for (i, run) in runs.iter().enumerate().skip(1) {
+        let mut check = |metric: &str, expected: String, actual: String| {
+            if expected != actual {
+                mismatches.push(format!(
+                    "{} was {} in run 1 but {} in run {}",
+                    metric,
+                    expected,
+                    actual,
+                    i + 1
+                ));
+            }
+        };
+        check(
+            "instructions",
+            metric(first.instructions),
+            metric(run.instructions),
+        );
+        check("pages", metric(first.pages), metric(run.pages));
+        check(
+            "memory_used",
+            metric(first.memory_used),
+            metric(run.memory_used),
+        );
+        check(
+            "num_preimage_requests",
+            metric(first.num_preimage_requests),
+            metric(run.num_preimage_requests),
+        );
+        check(
+            "total_preimage_size",
+            metric(first.total_preimage_size),
+            metric(run.total_preimage_size),
+        );
+        check("status", metric(first.status), metric(run.status));
+        check(
+            "state_hash",
+            metric(first.state_hash),
+            metric(run.state_hash),
+        );
+    }
+
+    if !mismatches.is_empty() {
+        return Err(eyre!("Nondeterministic runs: {}", mismatches.join(", ")));
+    }
+    Ok(())
+}
+
+/// Formats an optional metric for a mismatch message.
+fn metric<T: std::fmt::Debug>(value: Option<T>) -> String {
+    value.map_or_else(|| "missing".to_string(), |value| format!("{:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_stats() {
+        assert_eq!(RuntimeStats::new(&[]), None);
+
+        let stats = RuntimeStats::new(&[40, 10, 30, 20]).unwrap();
+        assert_eq!(stats.min, 10);
+        assert_eq!(stats.max, 40);
+        assert_eq!(stats.median, 25.0);
+        assert_eq!(stats.mean, 25.0);
+        assert_eq!(stats.p95, 40);
+        assert!((stats.std_dev - 12.909944).abs() < 1e-6);
+
+        let runtimes: Vec<u128> = (1..=100).collect();
+        let stats = RuntimeStats::new(&runtimes).unwrap();
+        assert_eq!(stats.p95, 95);
+        assert_eq!(stats.median, 50.5);
+
+        let stats = RuntimeStats::new(&[7]).unwrap();
+        assert_eq!((stats.median, stats.p95, stats.std_dev), (7.0, 7, 0.0));
+    }
+
+    #[test]
+    fn test_check_determinism() {
+        let run = |runtime, instructions| ProgramStats {
+            runtime,
+            instructions: Some(instructions),
+            num_preimage_requests: Some(42),
+            ..Default::default()
+        };
+
+        assert!(check_determinism(&[run(10, 100), run(20, 100), run(15, 100)]).is_ok());
+
+        let err = check_determinism(&[run(10, 100), run(20, 100), run(15, 101)]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Nondeterministic runs: instructions was 100 in run 1 but 101 in run 3"
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! Bisect Subcommand
+
+use alloy_primitives::B256;
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use fp_test_fixtures::encoding::fixture_stem;
+use fp_test_fixtures::{FaultProofFixture, FaultProofStatus, PreimageKey, PreimageKeyType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, trace};
+
+use super::run_op_program::{load_fixture, CannonCommand, HostArgs, OpProgramCommand};
+use super::state::CannonProof;
+use super::util::{unique_data_dir, VersionedState, VM_STATUS_VALID};
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "bisect";
+
+/// CLI arguments for the `bisect` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct Bisect {
+    /// Path to the op-program binary
+    #[clap(short, long, help = "Path to the op-program binary")]
+    pub op_program: PathBuf,
+    /// Path to the fixture file
+    #[clap(short, long, help = "Path to the fixture file")]
+    pub fixture: PathBuf,
+    /// The wrong output root to dispute
+    #[clap(
+        long,
+        help = "The wrong L2 output root to claim at the fixture's L2 block"
+    )]
+    pub claim: B256,
+    /// Path to the cannon binary
+    #[clap(short, long, help = "Path to the cannon binary")]
+    pub cannon: PathBuf,
+    /// The cannon state
+    #[clap(long, help = "Path to the cannon state")]
+    pub cannon_state: PathBuf,
+    /// The cannon metadata
+    #[clap(long, help = "Path to the cannon metadata")]
+    pub cannon_meta: PathBuf,
+    /// Native preimage host configuration
+    #[command(flatten)]
+    pub host: HostArgs,
+    /// The interval of the snapshots of the trace
+    #[clap(
+        long,
+        default_value_t = 100_000_000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Interval in steps of the state snapshots the bisection resumes cannon from"
+    )]
+    pub snapshot_interval: u64,
+    /// The first step the wrong claimant misrepresents
+    #[clap(
+        long,
+        help = "First step whose state the wrong claimant misrepresents (default: the last step)"
+    )]
+    pub lie_from: Option<u64>,
+    /// The depth of the execution trace bisection
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=63),
+        help = "Depth of the execution trace bisection (default: the smallest depth covering the trace)"
+    )]
+    pub trace_depth: Option<u32>,
+    /// Optional directory to write the outcome of the game to
+    #[clap(
+        long,
+        help = "Directory to write the disputed states, the step proof and the report to (default: bisect-artifacts/<fixture>)"
+    )]
+    pub artifacts: Option<PathBuf>,
+    /// Whether to discard the program's output
+    #[clap(long, help = "Discard the program's stdout and stderr")]
+    pub quiet: bool,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+/// A claim of the execution trace bisection, and whether the honest actor agrees with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Move {
+    /// The depth of the claim in the execution trace game.
+    pub depth: u32,
+    /// The step of the claimed state.
+    pub step: u64,
+    /// The state hash the wrong claimant posts.
+    pub claimed: B256,
+    /// The state hash of the honest trace.
+    pub honest: B256,
+    /// Whether the honest actor agrees with the claim, defending it rather than attacking it.
+    pub agreed: bool,
+}
+
+/// The execution trace bisection between the honest trace and the wrong claimant's.
+///
+/// The state at step 0 is the absolute prestate both agree on, and the root claim is the
+/// claimant's state at the end of the trace, which the honest actor disputes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bisection {
+    /// The last step whose state both agree on.
+    pub agreed: u64,
+    /// The first step whose state is known to be disputed.
+    pub disputed: u64,
+    /// The claims made so far.
+    pub moves: Vec<Move>,
+}
+
+impl Bisection {
+    /// Creates a bisection over a trace of `2^depth` steps.
+    pub fn new(depth: u32) -> Self {
+        Self {
+            agreed: 0,
+            disputed: 1 << depth,
+            moves: Vec::new(),
+        }
+    }
+
+    /// Returns the step of the next claim, or `None` once the dispute is down to a single step.
+    pub fn next(&self) -> Option<u64> {
+        (self.disputed - self.agreed > 1).then(|| self.agreed + (self.disputed - self.agreed) / 2)
+    }
+
+    /// Records the claimant's and the honest state hash at a step, narrowing the dispute.
+    pub fn respond(&mut self, step: u64, claimed: B256, honest: B256) {
+        let agreed = claimed == honest;
+        self.moves.push(Move {
+            depth: self.moves.len() as u32 + 1,
+            step,
+            claimed,
+            honest,
+            agreed,
+        });
+        if agreed {
+            self.agreed = step;
+        } else {
+            self.disputed = step;
+        }
+    }
+}
+
+/// Returns the state hash the wrong claimant posts at a step: the honest one before `lie_from`,
+/// and from then on the honest one with the VM status of a valid exit.
+pub fn claimed_hash(step: u64, honest: B256, lie_from: u64) -> B256 {
+    let mut claimed = honest;
+    if step >= lie_from {
+        claimed[0] = VM_STATUS_VALID;
+    }
+    claimed
+}
+
+/// Returns the number of the fixture's L2 head block, decoded from its header in the witness data.
+pub fn l2_head_number(fixture: &FaultProofFixture) -> Result<u64> {
+    let key = PreimageKey::from_hash(PreimageKeyType::Keccak256, fixture.inputs.l2_head);
+    let header = fixture.witness_data.get(&B256::from(key)).ok_or_else(|| {
+        eyre!(
+            "The witness data has no header for the L2 head {}",
+            fixture.inputs.l2_head
+        )
+    })?;
+    header_number(header).ok_or_else(|| {
+        eyre!(
+            "Failed to decode the header of the L2 head {}",
+            fixture.inputs.l2_head
+        )
+    })
+}
+
+/// Decodes the number, the ninth field, of an RLP-encoded block header.
+fn header_number(header: &[u8]) -> Option<u64> {
+    let (offset, len) = rlp_item(header)?;
+    let mut fields = header.get(offset..offset + len)?;
+    for _ in 0..8 {
+        let (offset, len) = rlp_item(fields)?;
+        fields = fields.get(offset + len..)?;
+    }
+    let (offset, len) = rlp_item(fields)?;
+    if len > 8 {
+        return None;
+    }
+    let number = fields.get(offset..offset + len)?;
+    Some(number.iter().fold(0, |n, &byte| (n << 8) | byte as u64))
+}
+
+/// Returns the offset and length of the payload of the RLP item at the start of `data`.
+fn rlp_item(data: &[u8]) -> Option<(usize, usize)> {
+    let prefix = *data.first()?;
+    let (offset, len) = match prefix {
+        0x00..=0x7f => return Some((0, 1)),
+        0x80..=0xb7 => (1, (prefix - 0x80) as usize),
+        0xc0..=0xf7 => (1, (prefix - 0xc0) as usize),
+        _ => {
+            let len_len = (prefix - if prefix < 0xc0 { 0xb7 } else { 0xf7 }) as usize;
+            let len = data
+                .get(1..1 + len_len)?
+                .iter()
+                .fold(0usize, |n, &byte| (n << 8) | byte as usize);
+            (1 + len_len, len)
+        }
+    };
+    (data.len() >= offset + len).then_some((offset, len))
+}
+
+/// Returns the smallest bisection depth whose trace covers the given number of steps.
+pub fn min_trace_depth(steps: u64) -> u32 {
+    steps.next_power_of_two().trailing_zeros().max(1)
+}
+
+/// The outcome of a simulated dispute game over a fixture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BisectReport {
+    /// The L2 block number of the agreed output root.
+    pub l2_head_number: u64,
+    /// The L2 block number of the disputed output root.
+    pub l2_block_number: u64,
+    /// The agreed output root the program derives from.
+    pub agreed_output_root: B256,
+    /// The wrong output root.
+    pub disputed_output_root: B256,
+    /// The hash of the absolute prestate.
+    pub absolute_prestate: B256,
+    /// The number of steps the program runs before exiting.
+    pub trace_length: u64,
+    /// The depth of the execution trace bisection.
+    pub trace_depth: u32,
+    /// The claims of the execution trace bisection.
+    pub moves: Vec<Move>,
+    /// The disputed step.
+    pub step: u64,
+    /// The hash of the state before the disputed step.
+    pub pre: B256,
+    /// The hash the wrong claimant posts for the state after the disputed step.
+    pub claimed_post: B256,
+    /// The hash of the state after the disputed step, as proven by the step proof.
+    pub post: B256,
+    /// The path of the step proof.
+    pub proof: PathBuf,
+}
+
+impl Bisect {
+    /// Runs the `bisect` subcommand.
+    pub async fn run(&self) -> Result<()> {
+        let mut fixture = load_fixture(&self.fixture)?;
+        fixture.inputs.l2_claim = self.claim;
+        let artifacts = self.artifacts.clone().unwrap_or_else(|| {
+            let dir = PathBuf::from("bisect-artifacts");
+            match fixture_stem(&self.fixture) {
+                Some(name) => dir.join(name),
+                None => dir,
+            }
+        });
+
+        // The fixture commits to no output roots between its agreed one and the claim, so the
+        // output root bisection can only be settled for a fixture of a single block.
+        let l2_head_number = l2_head_number(&fixture)?;
+        let blocks = fixture
+            .inputs
+            .l2_block_number
+            .saturating_sub(l2_head_number);
+        if blocks != 1 {
+            return Err(eyre!(
+                "`bisect` supports single-block fixtures only, but the fixture derives {} blocks from L2 block {} to {}",
+                blocks,
+                l2_head_number,
+                fixture.inputs.l2_block_number
+            ));
+        }
+        std::fs::create_dir_all(&artifacts)?;
+        println!(
+            "Output root bisection: agreed {} at block {}, disputed {} at block {}",
+            fixture.inputs.l2_output_root,
+            l2_head_number,
+            self.claim,
+            fixture.inputs.l2_block_number
+        );
+
+        let data_dir = unique_data_dir("bisect")?;
+        trace!(target: TARGET, "Using data directory: {:?}", data_dir);
+        let mut op_program_command =
+            OpProgramCommand::new(self.op_program.clone(), fixture, data_dir.clone());
+        op_program_command.quiet = self.quiet;
+        let mut cannon_command = CannonCommand::new(
+            self.cannon.clone(),
+            self.cannon_state.clone(),
+            self.cannon_meta.clone(),
+            op_program_command,
+        );
+        cannon_command.native_host = self.host.native_host;
+
+        let result = self
+            .bisect(&mut cannon_command, &artifacts, l2_head_number)
+            .await;
+        std::fs::remove_dir_all(&data_dir)?;
+        let report = result?;
+
+        let path = artifacts.join("bisect.json");
+        let file = std::fs::File::create(&path)
+            .map_err(|e| eyre!("Failed to create report {:?}: {}", path, e))?;
+        serde_json::to_writer_pretty(file, &report)?;
+        info!(target: TARGET, "Wrote the report to {:?}", path);
+
+        Ok(())
+    }
+
+    /// Runs the honest trace and bisects it against the wrong claimant's, down to the step
+    /// proof of the disputed step.
+    async fn bisect(
+        &self,
+        cannon_command: &mut CannonCommand,
+        artifacts: &Path,
+        l2_head_number: u64,
+    ) -> Result<BisectReport> {
+        let snapshots = cannon_command.op_program.data_dir.join("snapshots");
+        cannon_command.snapshot_at = Some(format!("%{}", self.snapshot_interval));
+        cannon_command.artifacts_dir = Some(snapshots.clone());
+        cannon_command.prepare().await?;
+        let absolute_prestate = cannon_command
+            .prestate_hash
+            .ok_or(eyre!("Failed to load the cannon state"))?;
+
+        info!(target: TARGET, "Running the honest trace");
+        let stats = cannon_command.run().await?;
+        let last = cannon_command.final_state()?;
+        let length = last.step();
+        match stats.status {
+            Some(FaultProofStatus::Valid) => {
+                return Err(eyre!(
+                    "The claim {} is valid after {} steps, there is nothing to dispute",
+                    self.claim,
+                    length
+                ))
+            }
+            Some(FaultProofStatus::Unfinished) | None => {
+                return Err(eyre!("The program didn't exit after {} steps", length))
+            }
+            Some(status) => println!("Trace of {} steps, exiting {:?}", length, status),
+        }
+
+        let lie_from = self.lie_from.unwrap_or(length);
+        if lie_from == 0 || lie_from > length {
+            return Err(eyre!(
+                "The claimant can only lie from a step between 1 and {}",
+                length
+            ));
+        }
+        let depth = self.trace_depth.unwrap_or_else(|| min_trace_depth(length));
+        if 1 << depth < length {
+            return Err(eyre!(
+                "A trace depth of {} doesn't cover {} steps",
+                depth,
+                length
+            ));
+        }
+
+        // The states cannon can resume from, by step.
+        let mut states = BTreeMap::from([(0, self.cannon_state.clone())]);
+        for step in (self.snapshot_interval..length).step_by(self.snapshot_interval as usize) {
+            let path = snapshots.join(format!("state-{}.bin.gz", step));
+            if path.exists() {
+                states.insert(step, path);
+            }
+        }
+        cannon_command.snapshot_at = None;
+        cannon_command.artifacts_dir = None;
+
+        let mut bisection = Bisection::new(depth);
+        while let Some(step) = bisection.next() {
+            // The trace is padded with the final state.
+            let honest = if step >= length {
+                last.state_hash()
+            } else {
+                let state = Self::resume(cannon_command, &states, step).await?;
+                let path = snapshots.join(format!("state-{}.bin.gz", step));
+                state.save(&path)?;
+                states.insert(step, path);
+                state.state_hash()
+            };
+            let claimed = claimed_hash(step, honest, lie_from);
+            bisection.respond(step, claimed, honest);
+            debug!(
+                target: TARGET,
+                "Depth {}: {} step {}",
+                bisection.moves.len(),
+                if claimed == honest { "agreed with" } else { "disputed" },
+                step
+            );
+        }
+
+        println!("Execution trace bisection at depth {}:", depth);
+        for m in &bisection.moves {
+            println!(
+                "  depth {:>2}  step {:>12}  {}  {}",
+                m.depth,
+                m.step,
+                if m.agreed { "agree   " } else { "disagree" },
+                m.claimed
+            );
+        }
+
+        // Cannon proves the disputed step from the agreed state.
+        let step = bisection.agreed;
+        cannon_command.state = states[&step].clone();
+        cannon_command.stop_at = Some(format!("={}", step + 1));
+        cannon_command.proof_at = Some(format!("={}", step));
+        cannon_command.artifacts_dir = Some(artifacts.to_path_buf());
+        cannon_command.run().await?;
+        let post_state = cannon_command.final_state()?;
+        let pre_state = VersionedState::load(&states[&step])?;
+        pre_state.save(&artifacts.join(format!("state-{}.bin.gz", step)))?;
+        post_state.save(&artifacts.join(format!("state-{}.bin.gz", step + 1)))?;
+
+        let path = artifacts.join(format!("proof-{}.json", step));
+        let proof = CannonProof::load(&path)?;
+        let (pre, post) = (pre_state.state_hash(), post_state.state_hash());
+        if proof.step != step || proof.pre != pre || proof.post != post {
+            return Err(eyre!(
+                "The proof of step {} from {} to {} doesn't prove step {} from {} to {}",
+                proof.step,
+                proof.pre,
+                proof.post,
+                step,
+                pre,
+                post
+            ));
+        }
+        let claimed_post = claimed_hash(step + 1, post, lie_from);
+        println!(
+            "Disputed step {}: pre-state {}, claimed post-state {}, proven post-state {}",
+            step, pre, claimed_post, post
+        );
+        println!("Step proof: {:?}", path);
+
+        Ok(BisectReport {
+            l2_head_number,
+            l2_block_number: cannon_command.op_program.fixture.inputs.l2_block_number,
+            agreed_output_root: cannon_command.op_program.fixture.inputs.l2_output_root,
+            disputed_output_root: self.claim,
+            absolute_prestate,
+            trace_length: length,
+            trace_depth: depth,
+            moves: bisection.moves,
+            step,
+            pre,
+            claimed_post,
+            post,
+            proof: path,
+        })
+    }
+
+    /// Runs cannon from the latest known state before a step up to the step.
+    async fn resume(
+        cannon_command: &mut CannonCommand,
+        states: &BTreeMap<u64, PathBuf>,
+        step: u64,
+    ) -> Result<VersionedState> {
+        let (from, path) = states
+            .range(..=step)
+            .next_back()
+            .ok_or(eyre!("No state to resume from before step {}", step))?;
+        debug!(
+            target: TARGET,
+            "Running from step {} to step {}", from, step
+        );
+        cannon_command.state = path.clone();
+        cannon_command.stop_at = Some(format!("={}", step));
+        cannon_command.run().await?;
+        let state = cannon_command.final_state()?;
+        if state.step() != step {
+            return Err(eyre!(
+                "Cannon stopped at step {} instead of step {}",
+                state.step(),
+                step
+            ));
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::util::{VM_STATUS_INVALID, VM_STATUS_UNFINISHED};
+
+    /// Returns the honest state hash at a step of a trace of `length` steps.
+    fn honest_hash(step: u64, length: u64) -> B256 {
+        let mut hash = B256::with_last_byte(step.min(length) as u8);
+        hash[0] = if step >= length {
+            VM_STATUS_INVALID
+        } else {
+            VM_STATUS_UNFINISHED
+        };
+        hash
+    }
+
+    #[test]
+    fn test_min_trace_depth() {
+        assert_eq!(min_trace_depth(1), 1);
+        assert_eq!(min_trace_depth(2), 1);
+        assert_eq!(min_trace_depth(3), 2);
+        assert_eq!(min_trace_depth(1024), 10);
+        assert_eq!(min_trace_depth(1025), 11);
+    }
+
+    #[test]
+    fn test_l2_head_number() {
+        let mut fields = Vec::new();
+        for len in [32, 32, 20, 32, 32, 32] {
+            fields.push(0x80 + len as u8);
+            fields.extend(vec![0xaa; len]);
+        }
+        // The bloom, a long string, the difficulty and the number.
+        fields.extend([0xb9, 0x01, 0x00]);
+        fields.extend([0; 256]);
+        fields.extend([0x80, 0x83, 0x1e, 0x84, 0x80, 0x01]);
+        let mut header = vec![0xf9, (fields.len() >> 8) as u8, fields.len() as u8];
+        header.extend(&fields);
+
+        let mut fixture = FaultProofFixture::default();
+        assert!(l2_head_number(&fixture).is_err());
+        fixture.inputs.l2_head = alloy_primitives::keccak256(&header);
+        let key = PreimageKey::keccak256(&header);
+        fixture
+            .witness_data
+            .insert(key.into(), header.clone().into());
+        assert_eq!(l2_head_number(&fixture).unwrap(), 2_000_000);
+
+        // A header cut short of its number.
+        header.truncate(header.len() - 6);
+        assert_eq!(header_number(&header), None);
+    }
+
+    #[test]
+    fn test_bisection() {
+        let length = 11;
+        for depth in [4, 6] {
+            for lie_from in 1..=length {
+                let mut bisection = Bisection::new(depth);
+                while let Some(step) = bisection.next() {
+                    let honest = honest_hash(step, length);
+                    bisection.respond(step, claimed_hash(step, honest, lie_from), honest);
+                }
+                assert_eq!(
+                    (bisection.agreed, bisection.disputed),
+                    (lie_from - 1, lie_from)
+                );
+                assert_eq!(bisection.moves.len(), depth as usize);
+                for m in &bisection.moves {
+                    assert_eq!(m.agreed, m.step < lie_from);
+                }
+            }
+        }
+    }
+}
@@ -3,27 +3,30 @@
 use alloy_primitives::hex::FromHex;
 use alloy_primitives::BlockHash;
 use alloy_primitives::{hex::ToHexExt, B256};
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Args, Parser};
 use color_eyre::{eyre::eyre, Result};
 use fp_test_fixtures::{
-    self, ChainDefinition, FaultProofFixture, FaultProofInputs, FaultProofStatus, Genesis,
+    self, ChainDefinition, FaultProofFixture, FaultProofInputs, FaultProofStatus, FixtureMetadata,
+    Genesis, ResourceBudgets, FIXTURE_VERSION,
 };
 use kona_derive::online::*;
 use reqwest::Url;
+use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{
-    env,
     io::{stderr, stdout},
     path::PathBuf,
 };
 use superchain_registry::ROLLUP_CONFIGS;
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
+use crate::cmd::run_op_program::FixtureRunner;
 use crate::cmd::util::RollupConfig;
 
-use super::util::{RollupProvider, SafeHeadResponse};
+use super::util::{unique_data_dir, L2BlockProvider, RollupProvider, SafeHeadResponse};
 
 /// The logging target to use for [tracing].
 const TARGET: &str = "from-op-program";
@@ -37,6 +40,19 @@ pub struct FromOpProgram {
     /// The L2 block number to validate.
     #[clap(long, help = "L2 block number to validate")]
     pub l2_block: u64,
+    /// Optional agreed L2 block number to start derivation from.
+    #[clap(
+        long,
+        conflicts_with = "block_count",
+        help = "Optional agreed L2 block number to start derivation from (defaults to the parent of the L2 block)"
+    )]
+    pub l2_agreed_block: Option<u64>,
+    /// Optional number of L2 blocks to derive and execute.
+    #[clap(
+        long,
+        help = "Optional number of L2 blocks to derive and execute, ending at the L2 block (defaults to 1)"
+    )]
+    pub block_count: Option<u64>,
     /// Optional L1 block number which can derive the given L2 block.
     #[clap(
         long,
@@ -67,11 +83,100 @@ pub struct FromOpProgram {
     /// The output file for the test fixture.
     #[clap(long, help = "Output file for the test fixture")]
     pub output: PathBuf,
+    /// Optional invalid claim to produce a negative test fixture with.
+    #[clap(
+        long,
+        help = "Produce an invalid-claim fixture, claiming `random`, the `parent` output root, or the given hash"
+    )]
+    pub invalid_claim: Option<InvalidClaim>,
+    /// Optional name of the script that produced the claimed blocks.
+    #[clap(
+        long,
+        help = "Name of the script that produced the claimed blocks, recorded in the metadata"
+    )]
+    pub script: Option<String>,
+    /// Optional arguments of the script that produced the claimed blocks.
+    #[clap(
+        long,
+        value_delimiter = ' ',
+        help = "Space-separated arguments of the script, recorded in the metadata"
+    )]
+    pub script_args: Vec<String>,
+    /// Optional resource budgets for the fixture.
+    #[command(flatten)]
+    pub budgets: BudgetArgs,
+    /// Optional tags to record in the metadata.
+    #[clap(long = "tag", help = "Tag to record in the metadata (may be repeated)")]
+    pub tags: Vec<String>,
     /// Verbosity level (0-4)
     #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
     pub v: u8,
 }
 
+/// CLI arguments for the resource budgets of a fixture.
+#[derive(Args, Clone, Debug, Default)]
+pub struct BudgetArgs {
+    /// Optional maximum number of cannon steps
+    #[clap(
+        long,
+        help = "Maximum number of cannon steps a run of the fixture may take"
+    )]
+    pub max_steps: Option<u64>,
+    /// Optional maximum memory
+    #[clap(
+        long,
+        help = "Maximum memory in bytes a cannon run of the fixture may use"
+    )]
+    pub max_memory: Option<u64>,
+    /// Optional maximum total preimage size
+    #[clap(
+        long,
+        help = "Maximum total size in bytes of the preimages a run of the fixture may request"
+    )]
+    pub max_preimage_size: Option<u64>,
+    /// Optional maximum runtime
+    #[clap(long, help = "Maximum runtime in milliseconds of a run of the fixture")]
+    pub max_runtime_ms: Option<u64>,
+}
+
+impl BudgetArgs {
+    /// Returns the configured budgets, or `None` if no budget is set.
+    pub fn budgets(&self) -> Option<ResourceBudgets> {
+        let budgets = ResourceBudgets {
+            max_steps: self.max_steps,
+            max_memory: self.max_memory,
+            max_preimage_size: self.max_preimage_size,
+            max_runtime_ms: self.max_runtime_ms,
+        };
+        (budgets != ResourceBudgets::default()).then_some(budgets)
+    }
+}
+
+/// The claim to use for an invalid-claim fixture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidClaim {
+    /// A random claim.
+    Random,
+    /// The agreed parent output root.
+    Parent,
+    /// A user-supplied claim.
+    Hash(B256),
+}
+
+impl FromStr for InvalidClaim {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(InvalidClaim::Random),
+            "parent" => Ok(InvalidClaim::Parent),
+            hash => B256::from_str(hash)
+                .map(InvalidClaim::Hash)
+                .map_err(|_| format!("expected `random`, `parent` or a 32-byte hash, got {hash}")),
+        }
+    }
+}
+
 impl FromOpProgram {
     /// Runs the from-op-program subcommand.
     pub async fn run(&self) -> Result<()> {
@@ -80,12 +185,7 @@ impl FromOpProgram {
         let inputs = self.fault_proof_inputs().await?;
         debug!(target: TARGET, "Using the following fault proof inputs: {:?}", inputs);
 
-        let dirname = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_millis()
-            .to_string();
-        let data_dir = env::temp_dir().join("from-op-program").join(dirname);
-        std::fs::create_dir_all(&data_dir)?;
+        let data_dir = unique_data_dir("from-op-program")?;
 
         let input_dir = data_dir.join("input");
         if input_dir.exists() {
@@ -219,21 +319,68 @@ impl FromOpProgram {
             })
         })?;
 
-        let fixture = FaultProofFixture {
+        let mut fixture = FaultProofFixture {
+            version: FIXTURE_VERSION,
             inputs,
             expected_status: FaultProofStatus::Valid,
             witness_data,
+            metadata: Some(self.metadata().await?),
+            budgets: self.budgets.budgets(),
         };
+        fixture.validate()?;
         info!(target: TARGET, "Successfully built fault proof test fixture");
 
+        if let Some(invalid_claim) = &self.invalid_claim {
+            fixture = self.invalidate(fixture, invalid_claim).await?;
+        }
+
         // Write the fault proof fixture to the specified output location.
-        let file = std::fs::File::create(&self.output)?;
-        serde_json::to_writer_pretty(file, &fixture)?;
+        fixture.save(&self.output)?;
         info!(target: TARGET, "Wrote fault proof fixture to: {:?}", self.output);
 
         Ok(())
     }
 
+    /// Replaces the claim of a valid fixture with an invalid one, keeping its witness data.
+    ///
+    /// The new fixture is run through op-program to confirm that the claim is rejected.
+    async fn invalidate(
+        &self,
+        mut fixture: FaultProofFixture,
+        invalid_claim: &InvalidClaim,
+    ) -> Result<FaultProofFixture> {
+        let claim = match invalid_claim {
+            InvalidClaim::Random => B256::random(),
+            InvalidClaim::Parent => fixture.inputs.l2_output_root,
+            InvalidClaim::Hash(hash) => *hash,
+        };
+        if claim == fixture.inputs.l2_claim {
+            return Err(eyre!("Invalid claim {} matches the valid claim", claim));
+        }
+        info!(target: TARGET, "Replacing claim {} with invalid claim {}", fixture.inputs.l2_claim, claim);
+
+        fixture.inputs.l2_claim = claim;
+        fixture.expected_status = FaultProofStatus::Invalid;
+
+        let runner = FixtureRunner::new(
+            self.op_program.clone(),
+            Default::default(),
+            Default::default(),
+        )?;
+        let stats = runner.run_fixture(fixture.clone()).await?;
+        let status = stats.status.unwrap_or(FaultProofStatus::Unknown);
+        if status != FaultProofStatus::Invalid {
+            error!(target: TARGET, "op-program did not reject the invalid claim, status: {:?}", status);
+            return Err(eyre!(
+                "op-program did not reject the invalid claim, status: {:?}",
+                status
+            ));
+        }
+        info!(target: TARGET, "Confirmed that op-program rejects the invalid claim");
+
+        Ok(fixture)
+    }
+
     /// Returns a new [AlloyChainProvider] using the l1 rpc url.
     pub fn l1_provider(&self) -> Result<AlloyChainProvider> {
         Ok(AlloyChainProvider::new_http(self.l1_rpc_url()?))
@@ -295,8 +442,16 @@ impl FromOpProgram {
 
         let rollup_provider = self.rollup_provider()?;
 
+        let agreed_block = self.agreed_block()?;
+        debug!(
+            target: TARGET,
+            "Using agreed L2 block {} for a claim over {} blocks",
+            agreed_block,
+            self.l2_block - agreed_block
+        );
+
         let claim_output = rollup_provider.output_at_block(self.l2_block).await?;
-        let parent_output = rollup_provider.output_at_block(self.l2_block - 1).await?;
+        let parent_output = rollup_provider.output_at_block(agreed_block).await?;
 
         let chain_definition: ChainDefinition;
 
@@ -336,6 +491,76 @@ impl FromOpProgram {
         })
     }
 
+    /// Returns the provenance metadata of the fixture.
+    async fn metadata(&self) -> Result<FixtureMetadata> {
+        let op_program_version = match std::process::Command::new(&self.op_program)
+            .arg("--version")
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            }
+            _ => {
+                warn!(target: TARGET, "Failed to get the op-program version");
+                None
+            }
+        };
+
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut std::fs::File::open(&self.op_program)?, &mut hasher)?;
+        let op_program_hash: [u8; 32] = hasher.finalize().into();
+
+        let l2_provider = L2BlockProvider::new_http(self.l2_rpc_url()?);
+        let (mut l2_gas_used, mut l2_tx_count) = (0, 0);
+        for block_number in self.agreed_block()? + 1..=self.l2_block {
+            let block = l2_provider.block_summary(block_number).await?;
+            l2_gas_used += block.gas_used.to::<u64>();
+            l2_tx_count += block.transactions.len() as u64;
+        }
+
+        Ok(FixtureMetadata {
+            generator: Some(format!("opfp {}", env!("CARGO_PKG_VERSION"))),
+            op_program_version,
+            op_program_hash: Some(op_program_hash.into()),
+            created_at: Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+            script: self.script.clone(),
+            script_args: self
+                .script_args
+                .iter()
+                .filter(|arg| !arg.is_empty())
+                .cloned()
+                .collect(),
+            l2_gas_used: Some(l2_gas_used),
+            l2_tx_count: Some(l2_tx_count),
+            tags: self.tags.clone(),
+        })
+    }
+
+    /// Returns the agreed L2 block number to start derivation from.
+    pub fn agreed_block(&self) -> Result<u64> {
+        let agreed_block = match (self.l2_agreed_block, self.block_count) {
+            (Some(agreed_block), _) => agreed_block,
+            (None, Some(0)) => return Err(eyre!("Block count must be at least 1")),
+            (None, Some(count)) => self
+                .l2_block
+                .checked_sub(count)
+                .ok_or_else(|| eyre!("Block count {} exceeds L2 block {}", count, self.l2_block))?,
+            (None, None) => self
+                .l2_block
+                .checked_sub(1)
+                .ok_or_else(|| eyre!("Cannot validate the genesis block"))?,
+        };
+
+        if agreed_block >= self.l2_block {
+            return Err(eyre!(
+                "Agreed L2 block {} must be before L2 block {}",
+                agreed_block,
+                self.l2_block
+            ));
+        }
+        Ok(agreed_block)
+    }
+
     async fn find_next_safe_head(&self) -> Result<SafeHeadResponse> {
         let cfg = self.rollup_config().await?;
         let mut l2_provider = self.l2_provider(Arc::new(cfg.into()))?;
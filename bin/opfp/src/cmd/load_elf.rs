@@ -0,0 +1,385 @@
+//! Load ELF Subcommand
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use goblin::elf::header::EM_MIPS;
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::Elf;
+use std::path::PathBuf;
+use tracing::info;
+
+use super::triage::{CannonMetadata, Symbol};
+use super::util::{
+    Arch, CpuScalars, FPVMState, Memory, MultiThreadedFPVMState, SingleThreadedFPVMState,
+    ThreadState, VersionedState, PAGE_SIZE, VERSION_MULTI_THREADED_64_V2,
+    VERSION_SINGLE_THREADED_2,
+};
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "load-elf";
+
+/// The program header type of the MIPS ABI flags, which cannon doesn't load.
+const PT_MIPS_ABIFLAGS: u32 = 0x7000_0003;
+
+/// The functions cannon patches out of single-threaded programs, as they rely on the garbage
+/// collector, background goroutines or floating point support the VM doesn't have.
+const PATCHED_FUNCTIONS: [&str; 14] = [
+    "runtime.gcenable",
+    "runtime.init.5",
+    "runtime.main.func1",
+    "runtime.deductSweepCredit",
+    "runtime.(*gcControllerState).commit",
+    "github.com/prometheus/client_golang/prometheus.init",
+    "github.com/prometheus/client_golang/prometheus.init.0",
+    "github.com/prometheus/procfs.init",
+    "github.com/prometheus/common/model.init",
+    "github.com/prometheus/client_model/go.init",
+    "github.com/prometheus/client_model/go.init.0",
+    "github.com/prometheus/client_model/go.init.1",
+    "flag.init",
+    "runtime.check",
+];
+
+/// `jr $ra; nop`, returning from a function as soon as it is called.
+const RETURN_INSTRUCTIONS: [u8; 8] = [0x03, 0xe0, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00];
+
+/// The 16 bytes of "randomness" cannon passes the program as `AT_RANDOM`.
+const AT_RANDOM_BYTES: &[u8; 16] = b"4;byfairdiceroll";
+
+/// CLI arguments for the `load-elf` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct LoadElf {
+    /// Path to the op-program client ELF
+    #[clap(long, help = "Path to the op-program client MIPS ELF")]
+    pub path: PathBuf,
+    /// The version of the state to create
+    #[clap(
+        long,
+        help = "State version to create (default: 2 for 32-bit programs, 4 for 64-bit programs)"
+    )]
+    pub state_version: Option<u8>,
+    /// Path to write the prestate to
+    #[clap(
+        long,
+        default_value = "state.bin.gz",
+        help = "Path to write the prestate to, gzip-compressed if it ends in .gz"
+    )]
+    pub output: PathBuf,
+    /// Path to write the symbol metadata to
+    #[clap(
+        long,
+        default_value = "meta.json",
+        help = "Path to write the cannon metadata to"
+    )]
+    pub meta: PathBuf,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+impl LoadElf {
+    /// Runs the `load-elf` subcommand.
+    pub async fn run(&self) -> Result<()> {
+        let data = std::fs::read(&self.path)
+            .map_err(|e| eyre!("Failed to read ELF {:?}: {}", self.path, e))?;
+        let (state, metadata) = load_elf(&data, self.state_version)?;
+        info!(
+            target: TARGET,
+            "Loaded {:?} into a version {} state with {} pages and {} symbols",
+            self.path,
+            state.version,
+            state.memory().pages.len(),
+            metadata.symbols.len()
+        );
+
+        state.save(&self.output)?;
+        metadata.save(&self.meta)?;
+        info!(
+            target: TARGET,
+            "Wrote the prestate to {:?} and the metadata to {:?}", self.output, self.meta
+        );
+        println!("Absolute prestate: {}", state.state_hash());
+
+        Ok(())
+    }
+}
+
+/// Builds the prestate of a big-endian MIPS ELF program the way `cannon load-elf` does, along
+/// with the metadata of its symbols.
+///
+/// The state version defaults to the latest single-threaded version for 32-bit programs, and
+/// the latest supported multi-threaded version for 64-bit programs.
+pub fn load_elf(data: &[u8], version: Option<u8>) -> Result<(VersionedState, CannonMetadata)> {
+    let elf = Elf::parse(data).map_err(|e| eyre!("Failed to parse ELF: {}", e))?;
+    if elf.header.e_machine != EM_MIPS || elf.little_endian {
+        return Err(eyre!("Expected a big-endian MIPS ELF"));
+    }
+    let arch = if elf.is_64 {
+        Arch::Mips64
+    } else {
+        Arch::Mips32
+    };
+    let version = version.unwrap_or(match arch {
+        Arch::Mips32 => VERSION_SINGLE_THREADED_2,
+        Arch::Mips64 => VERSION_MULTI_THREADED_64_V2,
+    });
+    let (version_arch, multi_threaded) = VersionedState::layout(version)
+        .ok_or_else(|| eyre!("Unsupported state version {}", version))?;
+    if version_arch != arch {
+        return Err(eyre!(
+            "State version {} is for {:?} programs, but the ELF is {:?}",
+            version,
+            version_arch,
+            arch
+        ));
+    }
+    let heap_start = match arch {
+        Arch::Mips32 => 0x0500_0000,
+        Arch::Mips64 => 0x1000_0000_0000_0000,
+    };
+
+    let mut memory = Memory::default();
+    for (i, header) in elf.program_headers.iter().enumerate() {
+        if header.p_type == PT_MIPS_ABIFLAGS {
+            continue;
+        }
+        if header.p_filesz > header.p_memsz
+            || (header.p_filesz != header.p_memsz && header.p_type != PT_LOAD)
+        {
+            return Err(eyre!(
+                "Segment {} has a file size of {:#x} but a memory size of {:#x}",
+                i,
+                header.p_filesz,
+                header.p_memsz
+            ));
+        }
+        let end = header.p_vaddr.saturating_add(header.p_memsz);
+        if end >= heap_start {
+            return Err(eyre!(
+                "Segment {} at {:#x}..{:#x} overlaps with the heap at {:#x}",
+                i,
+                header.p_vaddr,
+                end,
+                heap_start
+            ));
+        }
+        let contents = data
+            .get(header.file_range())
+            .ok_or_else(|| eyre!("Segment {} extends past the end of the ELF", i))?;
+        memory.set_range(header.p_vaddr, contents);
+        let zeroed = (header.p_memsz - header.p_filesz) as usize;
+        memory.set_range(header.p_vaddr + header.p_filesz, &vec![0; zeroed]);
+    }
+
+    let mut symbols: Vec<Symbol> = elf
+        .syms
+        .iter()
+        .skip(1)
+        .map(|symbol| Symbol {
+            name: elf
+                .strtab
+                .get_at(symbol.st_name)
+                .unwrap_or_default()
+                .to_string(),
+            start: symbol.st_value,
+            size: symbol.st_size,
+        })
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.start);
+    let metadata = CannonMetadata { symbols };
+
+    if !multi_threaded {
+        patch_go_gc(&mut memory, &metadata);
+    }
+    let stack_pointer = patch_stack(&mut memory, arch);
+
+    let state = if multi_threaded {
+        let mut thread = ThreadState {
+            futex_addr: all_ones(arch),
+            pc: elf.entry,
+            next_pc: elf.entry + 4,
+            ..Default::default()
+        };
+        thread.registers[29] = stack_pointer;
+        FPVMState::MultiThreaded(MultiThreadedFPVMState {
+            memory,
+            heap: heap_start,
+            wakeup: all_ones(arch),
+            next_thread_id: thread.thread_id + 1,
+            left_thread_stack: vec![thread],
+            ..Default::default()
+        })
+    } else {
+        let mut state = SingleThreadedFPVMState {
+            memory,
+            cpu: CpuScalars {
+                pc: elf.entry as u32,
+                next_pc: elf.entry as u32 + 4,
+                ..Default::default()
+            },
+            heap: heap_start as u32,
+            ..Default::default()
+        };
+        state.registers[29] = stack_pointer as u32;
+        FPVMState::SingleThreaded(state)
+    };
+
+    Ok((VersionedState { version, state }, metadata))
+}
+
+/// Returns a word with every bit set, the empty futex address.
+fn all_ones(arch: Arch) -> u64 {
+    match arch {
+        Arch::Mips32 => u32::MAX.into(),
+        Arch::Mips64 => u64::MAX,
+    }
+}
+
+/// Patches the functions the single-threaded VM can't run to return immediately, and disables
+/// memory profiling.
+fn patch_go_gc(memory: &mut Memory, metadata: &CannonMetadata) {
+    for symbol in &metadata.symbols {
+        if PATCHED_FUNCTIONS.contains(&symbol.name.as_str()) {
+            memory.set_range(symbol.start, &RETURN_INSTRUCTIONS);
+        } else if symbol.name == "runtime.MemProfileRate" {
+            memory.set_range(symbol.start, &[0; 4]);
+        }
+    }
+}
+
+/// Allocates the stack and lays out the program's arguments, environment and auxiliary vector
+/// on it, returning the initial stack pointer.
+fn patch_stack(memory: &mut Memory, arch: Arch) -> u64 {
+    let (sp, word) = match arch {
+        Arch::Mips32 => (0x7fff_d000, 4),
+        Arch::Mips64 => (0x7fff_ffff_d000, 8),
+    };
+    // One page for the initial stack data, and four for the stack to grow into.
+    memory.set_range(sp - 4 * PAGE_SIZE as u64, &[0; 5 * PAGE_SIZE]);
+
+    // argc, the argv terminator and the envp terminator, as cannon writes them.
+    memory.set_word(arch, sp + word, 0x42);
+    memory.set_word(arch, sp + word * 2, 0x35);
+    memory.set_word(arch, sp + word * 3, 0);
+    // The auxiliary vector: AT_PAGESZ, AT_RANDOM and its terminator.
+    memory.set_word(arch, sp + word * 4, 6);
+    memory.set_word(arch, sp + word * 5, PAGE_SIZE as u64);
+    memory.set_word(arch, sp + word * 6, 25);
+    memory.set_word(arch, sp + word * 7, sp + word * 9);
+    memory.set_word(arch, sp + word * 8, 0);
+    memory.set_range(sp + word * 9, AT_RANDOM_BYTES);
+
+    sp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a 32-bit big-endian MIPS ELF with a single loadable segment.
+    fn elf32(entry: u32, vaddr: u32, contents: &[u8], memsz: u32) -> Vec<u8> {
+        let mut elf = vec![0x7f, b'E', b'L', b'F', 1, 2, 1];
+        elf.resize(16, 0);
+        for half in [2u16, EM_MIPS] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        for word in [1u32, entry, 52, 0, 0] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        for half in [52u16, 32, 1, 40, 0, 0] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        let header = [
+            PT_LOAD,
+            84,
+            vaddr,
+            vaddr,
+            contents.len() as u32,
+            memsz,
+            5,
+            0x1000,
+        ];
+        for word in header {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        elf.extend_from_slice(contents);
+        elf
+    }
+
+    /// Builds a 64-bit big-endian MIPS ELF with a single loadable segment.
+    fn elf64(entry: u64, vaddr: u64, contents: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 2, 1];
+        elf.resize(16, 0);
+        for half in [2u16, EM_MIPS] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        elf.extend_from_slice(&1u32.to_be_bytes());
+        for word in [entry, 64, 0] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        elf.extend_from_slice(&0u32.to_be_bytes());
+        for half in [64u16, 56, 1, 64, 0, 0] {
+            elf.extend_from_slice(&half.to_be_bytes());
+        }
+        elf.extend_from_slice(&PT_LOAD.to_be_bytes());
+        elf.extend_from_slice(&5u32.to_be_bytes());
+        let size = contents.len() as u64;
+        for word in [120, vaddr, vaddr, size, size, 0x1000] {
+            elf.extend_from_slice(&word.to_be_bytes());
+        }
+        elf.extend_from_slice(contents);
+        elf
+    }
+
+    #[test]
+    fn test_load_elf() {
+        let elf = elf32(0x1_0008, 0x1_0000, &[0xaa; 16], 0x2000);
+        let (state, metadata) = load_elf(&elf, None).unwrap();
+        assert_eq!(state.version, VERSION_SINGLE_THREADED_2);
+        assert!(metadata.symbols.is_empty());
+
+        let FPVMState::SingleThreaded(inner) = &state.state else {
+            panic!("expected a single-threaded state");
+        };
+        assert_eq!((inner.cpu.pc, inner.cpu.next_pc), (0x1_0008, 0x1_000c));
+        assert_eq!(inner.heap, 0x0500_0000);
+        assert_eq!(inner.registers[29], 0x7fff_d000);
+        let page = &inner.memory.pages[&0x10];
+        assert_eq!(page[..16], [0xaa; 16]);
+        assert!(page[16..].iter().all(|&byte| byte == 0));
+        assert!(inner.memory.pages.contains_key(&0x11));
+        // Five stack pages, with AT_RANDOM's bytes at sp + 36.
+        assert_eq!(inner.memory.page_ranges()[1], 0x7fff9..=0x7fffd);
+        let stack = &inner.memory.pages[&0x7fffd];
+        assert_eq!(stack[4..8], [0, 0, 0, 0x42]);
+        assert_eq!(&stack[36..52], AT_RANDOM_BYTES);
+
+        let (multi_threaded, _) = load_elf(&elf, Some(5)).unwrap();
+        let FPVMState::MultiThreaded(inner) = &multi_threaded.state else {
+            panic!("expected a multi-threaded state");
+        };
+        let thread = inner.current_thread().unwrap();
+        assert_eq!((thread.pc, thread.registers[29]), (0x1_0008, 0x7fff_d000));
+        assert_eq!(
+            (thread.futex_addr, inner.wakeup),
+            (0xffff_ffff, 0xffff_ffff)
+        );
+        assert_eq!(inner.next_thread_id, 1);
+
+        assert!(load_elf(&elf, Some(7)).is_err());
+        // 64-bit programs default to the latest version with a verified layout.
+        let elf = elf64(0x1_0000, 0x1_0000, &[0xbb; 8]);
+        let (state, _) = load_elf(&elf, None).unwrap();
+        assert_eq!(state.version, VERSION_MULTI_THREADED_64_V2);
+        let FPVMState::MultiThreaded(inner) = &state.state else {
+            panic!("expected a multi-threaded state");
+        };
+        assert_eq!(inner.current_thread().unwrap().futex_addr, u64::MAX);
+        assert_eq!(inner.heap, 0x1000_0000_0000_0000);
+        assert!(load_elf(&elf, Some(7)).is_err());
+
+        let overlapping = elf32(0x1_0000, 0x04ff_f000, &[0; 4], 0x2000);
+        assert!(load_elf(&overlapping, None).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Migrate Subcommand
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use fp_test_fixtures::{FaultProofFixture, FIXTURE_VERSION};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+use super::run_suite::find_fixtures;
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "migrate";
+
+/// CLI arguments for the `migrate` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct Migrate {
+    /// Paths to the fixture files or directories of fixture files
+    #[clap(
+        required = true,
+        help = "Paths to fixture files or directories of fixture files"
+    )]
+    pub fixtures: Vec<PathBuf>,
+    /// Whether to only check that the fixtures are up to date
+    #[clap(
+        long,
+        help = "Fail if any fixture needs to be migrated instead of rewriting it"
+    )]
+    pub check: bool,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+impl Migrate {
+    /// Runs the `migrate` subcommand.
+    pub async fn run(&self) -> Result<()> {
+        let mut outdated = Vec::new();
+        for path in &self.fixtures {
+            let fixtures = if path.is_dir() {
+                find_fixtures(path)?
+            } else {
+                vec![path.clone()]
+            };
+
+            for fixture in fixtures {
+                if self.migrate_fixture(&fixture)? {
+                    outdated.push(fixture);
+                }
+            }
+        }
+
+        if self.check && !outdated.is_empty() {
+            return Err(eyre!(
+                "{} fixtures need to be migrated to version {}: {:?}",
+                outdated.len(),
+                FIXTURE_VERSION,
+                outdated
+            ));
+        }
+        info!(
+            target: TARGET,
+            "{} fixtures {} version {}",
+            outdated.len(),
+            if self.check { "are behind" } else { "migrated to" },
+            FIXTURE_VERSION
+        );
+
+        Ok(())
+    }
+
+    /// Migrates a single fixture file in place, returning whether it was outdated.
+    fn migrate_fixture(&self, path: &Path) -> Result<bool> {
+        let (fixture, version) = FaultProofFixture::load_versioned(path)
+            .map_err(|e| eyre!("Failed to migrate {:?}: {}", path, e))?;
+        if version == FIXTURE_VERSION {
+            debug!(target: TARGET, "{:?} is up to date", path);
+            return Ok(false);
+        }
+        info!(
+            target: TARGET,
+            "{:?} is at version {}, current version is {}", path, version, FIXTURE_VERSION
+        );
+
+        if !self.check {
+            fixture.save(path)?;
+        }
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,158 @@
+//! Minimize Subcommand
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use fp_test_fixtures::FaultProofStatus;
+use std::path::PathBuf;
+use tracing::info;
+
+use super::run_op_program::{load_fixture, CannonArgs, FixtureRunner, HostArgs};
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "minimize";
+
+/// CLI arguments for the `minimize` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct Minimize {
+    /// Path to the fixture file
+    #[clap(short, long, help = "Path to the fixture file")]
+    pub fixture: PathBuf,
+    /// Path to the op-program client binary
+    #[clap(
+        long,
+        help = "Path to the op-program client binary, required when running without cannon"
+    )]
+    pub op_program_client: Option<PathBuf>,
+    /// Optional cannon configuration
+    #[command(flatten)]
+    pub cannon: CannonArgs,
+    /// The output file for the minimized fixture
+    #[clap(long, help = "Path to the output file for the minimized fixture")]
+    pub output: PathBuf,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+impl Minimize {
+    /// Runs the `minimize` subcommand.
+    pub async fn run(&self) -> Result<()> {
+        let host = HostArgs {
+            native_host: true,
+            op_program_client: self.op_program_client.clone(),
+        };
+        // The native host replaces op-program, so only the client and cannon are executed.
+        let runner = FixtureRunner::new(PathBuf::default(), self.cannon.clone(), host)?;
+
+        let mut fixture = load_fixture(&self.fixture)?;
+        let (stats, accessed_keys) = runner.run_fixture_tracked(fixture.clone()).await?;
+        info!(
+            target: TARGET,
+            "Replayed fixture with status {:?}, {} of {} witness entries were accessed",
+            stats.status,
+            accessed_keys.len(),
+            fixture.witness_data.len()
+        );
+
+        // Pruning a fixture that doesn't replay as expected would keep only the preimages of the
+        // wrong run, and the output may well be the original fixture.
+        check_status("Replayed fixture", stats.status, fixture.expected_status)?;
+
+        let original_size = fixture.witness_data.len();
+        fixture
+            .witness_data
+            .retain(|key, _| accessed_keys.contains(key));
+
+        let minimized_stats = runner.run_fixture(fixture.clone()).await?;
+        check_status(
+            "Minimized fixture",
+            minimized_stats.status,
+            fixture.expected_status,
+        )?;
+
+        fixture.save(&self.output)?;
+        info!(
+            target: TARGET,
+            "Wrote minimized fixture with {} of {} witness entries to {:?}",
+            fixture.witness_data.len(),
+            original_size,
+            self.output
+        );
+
+        Ok(())
+    }
+}
+
+/// Checks that a run of the fixture ended with the status the fixture expects.
+fn check_status(
+    run: &str,
+    status: Option<FaultProofStatus>,
+    expected: FaultProofStatus,
+) -> Result<()> {
+    if status != Some(expected) {
+        return Err(eyre!(
+            "{} exited with {:?}, but the fixture expects {:?}",
+            run,
+            status,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::util::unique_data_dir;
+    use fp_test_fixtures::{ChainDefinition, FaultProofFixture, Genesis};
+
+    #[test]
+    fn test_check_status() {
+        assert!(check_status(
+            "Replayed fixture",
+            Some(FaultProofStatus::Valid),
+            FaultProofStatus::Valid
+        )
+        .is_ok());
+        for status in [
+            None,
+            Some(FaultProofStatus::Invalid),
+            Some(FaultProofStatus::Panic),
+        ] {
+            assert!(check_status("Replayed fixture", status, FaultProofStatus::Valid).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_status_mismatch() {
+        let dir = unique_data_dir("minimize-test").unwrap();
+        let path = dir.join("fixture.json");
+        let mut rollup_config = superchain_primitives::RollupConfig::default();
+        rollup_config.genesis.system_config = Some(Default::default());
+        let mut fixture = FaultProofFixture::default();
+        fixture.inputs.chain_definition =
+            ChainDefinition::Unnamed(rollup_config, Genesis::default());
+        fixture.save(&path).unwrap();
+        let original = std::fs::read(&path).unwrap();
+
+        // The client exits with 1 right away, an invalid claim the fixture doesn't expect.
+        let minimize = Minimize {
+            fixture: path.clone(),
+            op_program_client: Some(PathBuf::from("false")),
+            cannon: CannonArgs::default(),
+            output: path.clone(),
+            v: 0,
+        };
+        let result = minimize.run().await;
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let error = result.unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Replayed fixture exited with Some(Invalid), but the fixture expects Valid"
+        );
+        assert_eq!(written, original);
+    }
+}
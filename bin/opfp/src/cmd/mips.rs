@@ -0,0 +1,797 @@
+//! A native interpreter of cannon's single-threaded MIPS32 VM.
+//!
+//! It follows the semantics of cannon's single-threaded `mipsevm` and the onchain `MIPS.sol`
+//! instruction by instruction, including their deviations from the MIPS spec, so that a state
+//! stepped here commits to the same state hash as one stepped by cannon.
+
+use alloy_primitives::B256;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::io::Write;
+use tracing::trace;
+
+use super::preimage::PreimageServer;
+use super::util::{
+    FPVMState, SingleThreadedFPVMState, VersionedState, PAGE_SIZE, VERSION_SINGLE_THREADED_2,
+};
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "mips";
+
+/// The syscall numbers the VM handles, as on MIPS o32 Linux.
+const SYS_READ: u32 = 4003;
+const SYS_WRITE: u32 = 4004;
+const SYS_BRK: u32 = 4045;
+const SYS_FCNTL: u32 = 4055;
+const SYS_MMAP: u32 = 4090;
+const SYS_CLONE: u32 = 4120;
+const SYS_EXIT_GROUP: u32 = 4246;
+
+/// The file descriptors the VM handles.
+const FD_STDIN: u32 = 0;
+const FD_STDOUT: u32 = 1;
+const FD_STDERR: u32 = 2;
+const FD_HINT_READ: u32 = 3;
+const FD_HINT_WRITE: u32 = 4;
+const FD_PREIMAGE_READ: u32 = 5;
+const FD_PREIMAGE_WRITE: u32 = 6;
+
+/// The error codes the VM returns in `$a3`.
+const EBADF: u32 = 0x9;
+const EINVAL: u32 = 0x16;
+
+/// The program break `brk` reports, as the VM allocates memory with `mmap` instead.
+const PROGRAM_BREAK: u32 = 0x4000_0000;
+
+/// The end of the heap `mmap` allocates from, past which allocations fail.
+const HEAP_END: u32 = 0x6000_0000;
+
+/// Executes a single-threaded cannon state.
+#[derive(Debug)]
+pub struct Mips32<'a> {
+    /// The state being executed.
+    pub state: SingleThreadedFPVMState,
+    /// The version of the state, which selects the supported syscalls.
+    version: u8,
+    /// The source of the preimages the program reads.
+    preimages: &'a PreimageServer,
+    /// The length-prefixed preimage last read, with its key.
+    preimage: Option<(B256, Vec<u8>)>,
+    /// Whether to discard the program's stdout and stderr instead of forwarding them.
+    quiet: bool,
+}
+
+impl<'a> Mips32<'a> {
+    /// Creates an interpreter of a single-threaded state, reading preimages from the server.
+    pub fn new(state: VersionedState, preimages: &'a PreimageServer) -> Result<Self> {
+        match state.state {
+            FPVMState::SingleThreaded(inner) => Ok(Self {
+                state: inner,
+                version: state.version,
+                preimages,
+                preimage: None,
+                quiet: false,
+            }),
+            FPVMState::MultiThreaded(_) => Err(eyre!(
+                "Only single-threaded states can be interpreted, got version {}",
+                state.version
+            )),
+        }
+    }
+
+    /// Sets whether to discard the program's stdout and stderr.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Returns the executed state.
+    pub fn into_state(self) -> VersionedState {
+        VersionedState {
+            version: self.version,
+            state: FPVMState::SingleThreaded(self.state),
+        }
+    }
+
+    /// Steps until the program exits, or until the given step if one is set.
+    pub fn run(&mut self, stop_at: Option<u64>) -> Result<()> {
+        while !self.state.exited && stop_at.is_none_or(|step| self.state.step < step) {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<()> {
+        if self.state.exited {
+            return Ok(());
+        }
+        self.state.step += 1;
+
+        let insn = self.load(self.state.cpu.pc)?;
+        let opcode = insn >> 26;
+        let fun = insn & 0x3f;
+        trace!(
+            target: TARGET,
+            "step {} pc {:#010x} insn {:#010x}",
+            self.state.step,
+            self.state.cpu.pc,
+            insn
+        );
+
+        // J-type: j, jal
+        if opcode == 2 || opcode == 3 {
+            let link = if opcode == 3 { 31 } else { 0 };
+            // The target is in the 256 MiB region of the delay slot.
+            let target = (self.state.cpu.next_pc & 0xf000_0000) | ((insn & 0x03ff_ffff) << 2);
+            return self.jump(link, target);
+        }
+
+        let rt_reg = (insn >> 16) & 0x1f;
+        let mut rs = self.state.registers[((insn >> 21) & 0x1f) as usize];
+        let mut rt = 0;
+        let mut rd_reg = rt_reg;
+        if opcode == 0 || opcode == 0x1c {
+            // R-type, writing to rd.
+            rt = self.state.registers[rt_reg as usize];
+            rd_reg = (insn >> 11) & 0x1f;
+        } else if opcode < 0x20 {
+            // I-type with an immediate, zero-extended for andi, ori and xori.
+            rt = match opcode {
+                0xc..=0xe => insn & 0xffff,
+                _ => sign_extend(insn & 0xffff, 16),
+            };
+        } else if opcode >= 0x28 || opcode == 0x22 || opcode == 0x26 {
+            // Stores, and lwl and lwr, which merge into rt.
+            rt = self.state.registers[rt_reg as usize];
+            rd_reg = rt_reg;
+        }
+
+        if (4..8).contains(&opcode) || opcode == 1 {
+            return self.branch(opcode, insn, rt_reg, rs);
+        }
+
+        // Loads and stores both read the word at M[R[rs] + SignExtImm].
+        let mut store_address = None;
+        let mut mem = 0;
+        if opcode >= 0x20 {
+            rs = rs.wrapping_add(sign_extend(insn & 0xffff, 16));
+            let address = rs & !3;
+            mem = self.load(address)?;
+            if opcode >= 0x28 && opcode != 0x30 {
+                store_address = Some(address);
+                rd_reg = 0;
+            }
+        }
+
+        let value = execute(insn, rs, rt, mem)?;
+
+        if opcode == 0 && (8..0x1c).contains(&fun) {
+            match fun {
+                // jr, jalr
+                8 | 9 => return self.jump(if fun == 9 { rd_reg } else { 0 }, rs),
+                // movz, movn
+                0xa => return self.write_rd(rd_reg, rs, rt == 0),
+                0xb => return self.write_rd(rd_reg, rs, rt != 0),
+                0xc => return self.syscall(),
+                0x10..=0x13 | 0x18.. => return self.hi_lo(fun, rs, rt, rd_reg),
+                _ => {}
+            }
+        }
+
+        // sc always succeeds.
+        if opcode == 0x38 && rt_reg != 0 {
+            self.state.registers[rt_reg as usize] = 1;
+        }
+        if let Some(address) = store_address {
+            self.store(address, value);
+        }
+        self.write_rd(rd_reg, value, true)
+    }
+
+    /// Reads the aligned word at an address, where unallocated memory is zeroed.
+    fn load(&self, address: u32) -> Result<u32> {
+        if address & 3 != 0 {
+            return Err(eyre!("Unaligned memory access at {:#010x}", address));
+        }
+        let offset = address as usize % PAGE_SIZE;
+        Ok(self
+            .state
+            .memory
+            .pages
+            .get(&(address as u64 / PAGE_SIZE as u64))
+            .map_or(0, |page| {
+                u32::from_be_bytes(page[offset..offset + 4].try_into().unwrap())
+            }))
+    }
+
+    /// Writes the aligned word at an address.
+    fn store(&mut self, address: u32, value: u32) {
+        self.state
+            .memory
+            .set_range(address.into(), &value.to_be_bytes());
+    }
+
+    /// Reads a range of memory, where unallocated memory is zeroed.
+    fn load_range(&self, address: u32, len: u32) -> Vec<u8> {
+        (0..len)
+            .map(|i| {
+                let address = address.wrapping_add(i) as usize;
+                self.state
+                    .memory
+                    .pages
+                    .get(&((address / PAGE_SIZE) as u64))
+                    .map_or(0, |page| page[address % PAGE_SIZE])
+            })
+            .collect()
+    }
+
+    /// Writes a register unless it is `$zero` or the condition is false, and moves to the next
+    /// instruction.
+    fn write_rd(&mut self, rd_reg: u32, value: u32, condition: bool) -> Result<()> {
+        if rd_reg != 0 && condition {
+            self.state.registers[rd_reg as usize] = value;
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// Moves to the next instruction.
+    fn advance(&mut self) {
+        self.state.cpu.pc = self.state.cpu.next_pc;
+        self.state.cpu.next_pc = self.state.cpu.next_pc.wrapping_add(4);
+    }
+
+    /// Jumps to the target after the delay slot, linking the return address if `link` is set.
+    fn jump(&mut self, link: u32, target: u32) -> Result<()> {
+        let cpu = &mut self.state.cpu;
+        if cpu.next_pc != cpu.pc.wrapping_add(4) {
+            return Err(eyre!("Jump in delay slot at {:#010x}", cpu.pc));
+        }
+        let pc = cpu.pc;
+        cpu.pc = cpu.next_pc;
+        cpu.next_pc = target;
+        if link != 0 {
+            self.state.registers[link as usize] = pc.wrapping_add(8);
+        }
+        Ok(())
+    }
+
+    /// Executes a conditional branch, after its delay slot.
+    fn branch(&mut self, opcode: u32, insn: u32, rt_reg: u32, rs: u32) -> Result<()> {
+        let cpu = &mut self.state.cpu;
+        if cpu.next_pc != cpu.pc.wrapping_add(4) {
+            return Err(eyre!("Branch in delay slot at {:#010x}", cpu.pc));
+        }
+        let rt = self.state.registers[rt_reg as usize];
+        let should_branch = match opcode {
+            // beq, bne
+            4 => rs == rt,
+            5 => rs != rt,
+            // blez, bgtz
+            6 => rs as i32 <= 0,
+            7 => rs as i32 > 0,
+            // REGIMM: bltz, bgez, bgezal
+            _ => match rt_reg {
+                0 => (rs as i32) < 0,
+                1 => rs as i32 >= 0,
+                0x11 => {
+                    self.state.registers[31] = cpu.pc.wrapping_add(8);
+                    rs as i32 >= 0
+                }
+                _ => false,
+            },
+        };
+
+        let pc = cpu.pc;
+        cpu.pc = cpu.next_pc;
+        cpu.next_pc = if should_branch {
+            pc.wrapping_add(4)
+                .wrapping_add(sign_extend(insn & 0xffff, 16) << 2)
+        } else {
+            cpu.next_pc.wrapping_add(4)
+        };
+        Ok(())
+    }
+
+    /// Executes the instructions reading or writing the HI and LO registers.
+    fn hi_lo(&mut self, fun: u32, rs: u32, rt: u32, rd_reg: u32) -> Result<()> {
+        let cpu = &mut self.state.cpu;
+        let mut value = 0;
+        match fun {
+            0x10 => value = cpu.hi,
+            0x11 => cpu.hi = rs,
+            0x12 => value = cpu.lo,
+            0x13 => cpu.lo = rs,
+            // mult, multu
+            0x18 | 0x19 => {
+                let product = if fun == 0x18 {
+                    (rs as i32 as i64).wrapping_mul(rt as i32 as i64) as u64
+                } else {
+                    rs as u64 * rt as u64
+                };
+                cpu.hi = (product >> 32) as u32;
+                cpu.lo = product as u32;
+            }
+            // div, divu
+            0x1a | 0x1b => {
+                if rt == 0 {
+                    return Err(eyre!("Division by zero at {:#010x}", cpu.pc));
+                }
+                if fun == 0x1a {
+                    cpu.hi = (rs as i32).wrapping_rem(rt as i32) as u32;
+                    cpu.lo = (rs as i32).wrapping_div(rt as i32) as u32;
+                } else {
+                    cpu.hi = rs % rt;
+                    cpu.lo = rs / rt;
+                }
+            }
+            _ => {}
+        }
+        self.write_rd(rd_reg, value, true)
+    }
+
+    /// Executes a syscall, with its number in `$v0` and arguments in `$a0` to `$a2`, returning
+    /// in `$v0` and `$a3`.
+    fn syscall(&mut self) -> Result<()> {
+        let registers = &self.state.registers;
+        let (number, a0, a1, a2) = (registers[2], registers[4], registers[5], registers[6]);
+        let (mut v0, mut v1) = (0, 0);
+        match number {
+            SYS_MMAP => {
+                let mut size = a1;
+                if !size.is_multiple_of(PAGE_SIZE as u32) {
+                    size = size.wrapping_add(PAGE_SIZE as u32 - size % PAGE_SIZE as u32);
+                }
+                if a0 == 0 {
+                    let heap = self.state.heap.wrapping_add(size);
+                    // Fail on running out of heap, or a size overflowing as it's rounded up.
+                    if heap > HEAP_END || heap < self.state.heap || size < a1 {
+                        (v0, v1) = (u32::MAX, EINVAL);
+                    } else {
+                        v0 = self.state.heap;
+                        self.state.heap = heap;
+                    }
+                } else {
+                    v0 = a0;
+                }
+            }
+            SYS_BRK => v0 = PROGRAM_BREAK,
+            // Threads aren't supported, so clone reports a child that never runs.
+            SYS_CLONE => v0 = 1,
+            SYS_EXIT_GROUP => {
+                self.state.exited = true;
+                self.state.exit_code = a0 as u8;
+                return Ok(());
+            }
+            SYS_READ => (v0, v1) = self.read(a0, a1, a2)?,
+            SYS_WRITE => (v0, v1) = self.write(a0, a1, a2)?,
+            SYS_FCNTL => (v0, v1) = self.fcntl(a0, a1),
+            _ => {}
+        }
+
+        self.state.registers[2] = v0;
+        self.state.registers[7] = v1;
+        self.advance();
+        Ok(())
+    }
+
+    /// Reads from a file descriptor, returning `$v0` and `$a3`.
+    fn read(&mut self, fd: u32, address: u32, count: u32) -> Result<(u32, u32)> {
+        match fd {
+            FD_STDIN => Ok((0, 0)),
+            FD_PREIMAGE_READ => {
+                let word_address = address & !3;
+                let mut word = self.load(word_address)?.to_be_bytes();
+                let data = self.read_preimage()?;
+                let alignment = (address & 3) as usize;
+                let len = data.len().min(4 - alignment).min(count as usize);
+                word[alignment..alignment + len].copy_from_slice(&data[..len]);
+                self.store(word_address, u32::from_be_bytes(word));
+                self.state.perimage_offset += len as u32;
+                Ok((len as u32, 0))
+            }
+            // Hint responses are ignored, so report them as read in full.
+            FD_HINT_READ => Ok((count, 0)),
+            _ => Ok((u32::MAX, EBADF)),
+        }
+    }
+
+    /// Returns up to 32 bytes of the length-prefixed current preimage from the current offset.
+    fn read_preimage(&mut self) -> Result<&[u8]> {
+        let key = self.state.preimage_key;
+        if self
+            .preimage
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != key)
+        {
+            let preimage = self.preimages.get(key)?;
+            let mut data = (preimage.len() as u64).to_be_bytes().to_vec();
+            data.extend_from_slice(preimage);
+            self.preimage = Some((key, data));
+        }
+        let (_, data) = self.preimage.as_ref().unwrap();
+
+        let offset = self.state.perimage_offset as usize;
+        if offset >= data.len() {
+            return Err(eyre!(
+                "Preimage offset {} is out of bounds for key {} of length {}",
+                offset,
+                key,
+                data.len()
+            ));
+        }
+        Ok(&data[offset..data.len().min(offset + 32)])
+    }
+
+    /// Writes to a file descriptor, returning `$v0` and `$a3`.
+    fn write(&mut self, fd: u32, address: u32, count: u32) -> Result<(u32, u32)> {
+        match fd {
+            FD_STDOUT | FD_STDERR => {
+                if !self.quiet {
+                    let data = self.load_range(address, count);
+                    // Forwarding is best effort; a closed terminal shouldn't fail the run.
+                    let _ = if fd == FD_STDOUT {
+                        std::io::stdout().write_all(&data)
+                    } else {
+                        std::io::stderr().write_all(&data)
+                    };
+                }
+                Ok((count, 0))
+            }
+            FD_HINT_WRITE => {
+                let data = self.load_range(address, count);
+                let hints = &mut self.state.last_hint;
+                hints.extend_from_slice(&data);
+                // Complete hints are consumed, leaving a partial hint buffered in the state.
+                while hints.len() >= 4 {
+                    let len = u32::from_be_bytes(hints[..4].try_into().unwrap()) as usize;
+                    if hints.len() - 4 < len {
+                        break;
+                    }
+                    trace!(
+                        target: TARGET,
+                        "hint {}",
+                        String::from_utf8_lossy(&hints[4..4 + len])
+                    );
+                    hints.drain(..4 + len);
+                }
+                Ok((count, 0))
+            }
+            FD_PREIMAGE_WRITE => {
+                let word = self.load(address & !3)?.to_be_bytes();
+                let alignment = (address & 3) as usize;
+                let len = (count as usize).min(4 - alignment);
+                let key = &mut self.state.preimage_key.0;
+                key.copy_within(len.., 0);
+                key[32 - len..].copy_from_slice(&word[alignment..alignment + len]);
+                self.state.perimage_offset = 0;
+                Ok((len as u32, 0))
+            }
+            _ => Ok((u32::MAX, EBADF)),
+        }
+    }
+
+    /// Handles `fcntl`, which only reports the flags of the VM's file descriptors.
+    fn fcntl(&self, fd: u32, cmd: u32) -> (u32, u32) {
+        match cmd {
+            // F_GETFD
+            1 if self.version >= VERSION_SINGLE_THREADED_2 => match fd {
+                FD_STDIN..=FD_PREIMAGE_WRITE => (0, 0),
+                _ => (u32::MAX, EBADF),
+            },
+            // F_GETFL
+            3 => match fd {
+                FD_STDIN | FD_HINT_READ | FD_PREIMAGE_READ => (0, 0),
+                FD_STDOUT | FD_STDERR | FD_HINT_WRITE | FD_PREIMAGE_WRITE => (1, 0),
+                _ => (u32::MAX, EBADF),
+            },
+            _ => (u32::MAX, EINVAL),
+        }
+    }
+}
+
+/// Computes the result of an ALU, load or store instruction, where `mem` is the word a load or
+/// store addresses and stores return the updated word.
+fn execute(insn: u32, rs: u32, rt: u32, mem: u32) -> Result<u32> {
+    let mut opcode = insn >> 26;
+    let mut fun = insn & 0x3f;
+
+    if opcode < 0x20 {
+        // I-type arithmetic and logic maps onto the R-type function.
+        if (8..0xf).contains(&opcode) {
+            fun = match opcode {
+                8 => 0x20,   // addi
+                9 => 0x21,   // addiu
+                0xa => 0x2a, // slti
+                0xb => 0x2b, // sltiu
+                0xc => 0x24, // andi
+                0xd => 0x25, // ori
+                _ => 0x26,   // xori
+            };
+            opcode = 0;
+        }
+
+        if opcode == 0 {
+            let shamt = (insn >> 6) & 0x1f;
+            match fun {
+                // jr, jalr, movz, movn, syscall, sync, hi and lo
+                0x08..=0x0c | 0x0f..=0x13 | 0x18..=0x1b => return Ok(rs),
+                0x00 => return Ok(rt << shamt),
+                0x02 => return Ok(rt >> shamt),
+                0x03 => return Ok(((rt as i32) >> shamt) as u32),
+                0x04 => return Ok(rt << (rs & 0x1f)),
+                0x06 => return Ok(rt >> (rs & 0x1f)),
+                0x07 => return Ok(((rt as i32) >> (rs & 0x1f)) as u32),
+                // add, addu
+                0x20 | 0x21 => return Ok(rs.wrapping_add(rt)),
+                // sub, subu
+                0x22 | 0x23 => return Ok(rs.wrapping_sub(rt)),
+                0x24 => return Ok(rs & rt),
+                0x25 => return Ok(rs | rt),
+                0x26 => return Ok(rs ^ rt),
+                0x27 => return Ok(!(rs | rt)),
+                // slt, sltu
+                0x2a => return Ok(((rs as i32) < (rt as i32)) as u32),
+                0x2b => return Ok((rs < rt) as u32),
+                _ => {}
+            }
+        } else if opcode == 0xf {
+            // lui
+            return Ok(rt << 16);
+        } else if opcode == 0x1c {
+            match fun {
+                // mul
+                0x02 => return Ok((rs as i32).wrapping_mul(rt as i32) as u32),
+                // clz, clo
+                0x20 => return Ok(rs.leading_zeros()),
+                0x21 => return Ok(rs.leading_ones()),
+                _ => {}
+            }
+        }
+    } else {
+        // The shift of the addressed byte or halfword within the big-endian word.
+        let byte_shift = 24 - (rs & 3) * 8;
+        let half_shift = 16 - (rs & 2) * 8;
+        match opcode {
+            // lb, lh, lwl, lw, lbu, lhu, lwr
+            0x20 => return Ok(sign_extend((mem >> byte_shift) & 0xff, 8)),
+            0x21 => return Ok(sign_extend((mem >> half_shift) & 0xffff, 16)),
+            0x22 => {
+                let shift = (rs & 3) * 8;
+                return Ok((rt & !(u32::MAX << shift)) | (mem << shift));
+            }
+            0x23 => return Ok(mem),
+            0x24 => return Ok((mem >> byte_shift) & 0xff),
+            0x25 => return Ok((mem >> half_shift) & 0xffff),
+            0x26 => return Ok((rt & !(u32::MAX >> byte_shift)) | (mem >> byte_shift)),
+            // sb, sh, swl, sw, swr
+            0x28 => return Ok((mem & !(0xff << byte_shift)) | ((rt & 0xff) << byte_shift)),
+            0x29 => return Ok((mem & !(0xffff << half_shift)) | ((rt & 0xffff) << half_shift)),
+            0x2a => {
+                let shift = (rs & 3) * 8;
+                return Ok((mem & !(u32::MAX >> shift)) | (rt >> shift));
+            }
+            0x2b => return Ok(rt),
+            0x2e => return Ok((mem & !(u32::MAX << byte_shift)) | (rt << byte_shift)),
+            // ll, sc
+            0x30 => return Ok(mem),
+            0x38 => return Ok(rt),
+            _ => {}
+        }
+    }
+
+    Err(eyre!("Invalid instruction {:#010x}", insn))
+}
+
+/// Sign-extends the low `bits` bits of a value.
+fn sign_extend(value: u32, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    (((value << shift) as i32) >> shift) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::Bytes;
+    use fp_test_fixtures::{ChainDefinition, FaultProofFixture, Genesis, PreimageKey};
+
+    fn r_type(rs: u32, rt: u32, rd: u32, shamt: u32, fun: u32) -> u32 {
+        (rs << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | fun
+    }
+
+    fn i_type(opcode: u32, rs: u32, rt: u32, imm: u16) -> u32 {
+        (opcode << 26) | (rs << 21) | (rt << 16) | imm as u32
+    }
+
+    /// Returns a state with the program loaded at address 0.
+    fn state(program: &[u32]) -> VersionedState {
+        let mut inner = SingleThreadedFPVMState::default();
+        inner.cpu.next_pc = 4;
+        inner.heap = 0x0500_0000;
+        let code: Vec<u8> = program.iter().flat_map(|insn| insn.to_be_bytes()).collect();
+        inner.memory.set_range(0, &code);
+        VersionedState {
+            version: VERSION_SINGLE_THREADED_2,
+            state: FPVMState::SingleThreaded(inner),
+        }
+    }
+
+    fn preimages(key: B256, preimage: &'static [u8]) -> PreimageServer {
+        let mut rollup_config = superchain_primitives::RollupConfig::default();
+        rollup_config.genesis.system_config = Some(Default::default());
+        let mut fixture = FaultProofFixture::default();
+        fixture.inputs.chain_definition =
+            ChainDefinition::Unnamed(rollup_config, Genesis::default());
+        fixture
+            .witness_data
+            .insert(key, Bytes::from_static(preimage));
+        PreimageServer::new(&fixture).unwrap()
+    }
+
+    /// `syscall` with the number in `$v0` loaded by `addiu`.
+    fn syscall(number: u32) -> [u32; 2] {
+        [i_type(9, 0, 2, number as u16), r_type(0, 0, 0, 0, 0xc)]
+    }
+
+    #[test]
+    fn test_execute() {
+        assert_eq!(
+            execute(r_type(0, 0, 0, 4, 0x03), 0, 0x8000_0000, 0).unwrap(),
+            0xf800_0000
+        );
+        assert_eq!(
+            execute(r_type(0, 0, 0, 0, 0x2a), u32::MAX, 1, 0).unwrap(),
+            1
+        );
+        assert_eq!(
+            execute(r_type(0, 0, 0, 0, 0x2b), u32::MAX, 1, 0).unwrap(),
+            0
+        );
+        // lb of the second byte, lhu of the second halfword.
+        assert_eq!(execute(0x20 << 26, 1, 0, 0x1280_3456).unwrap(), 0xffff_ff80);
+        assert_eq!(execute(0x25 << 26, 2, 0, 0x1234_5678).unwrap(), 0x5678);
+        // lwl and lwr of an unaligned word.
+        assert_eq!(
+            execute(0x22 << 26, 1, 0xaabb_ccdd, 0x1122_3344).unwrap(),
+            0x2233_44dd
+        );
+        assert_eq!(
+            execute(0x26 << 26, 0, 0xaabb_ccdd, 0x1122_3344).unwrap(),
+            0xaabb_cc11
+        );
+        // sb into the third byte.
+        assert_eq!(
+            execute(0x28 << 26, 2, 0xab, 0x1122_3344).unwrap(),
+            0x1122_ab44
+        );
+        assert_eq!(execute((0x1c << 26) | 0x20, 0x00ff_0000, 0, 0).unwrap(), 8);
+        assert!(execute(0x3f << 26, 0, 0, 0).is_err());
+        // break, and the functions cannon doesn't implement.
+        for fun in [0x0d, 0x0e, 0x14, 0x15, 0x16, 0x17, 0x1c, 0x1d, 0x1e, 0x1f] {
+            assert!(
+                execute(r_type(1, 2, 3, 0, fun), 1, 2, 0).is_err(),
+                "{fun:#x}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_instruction() {
+        let preimages = preimages(B256::ZERO, b"");
+        // dsllv, which used to go through the HI and LO handling.
+        let mut mips = Mips32::new(state(&[r_type(8, 9, 10, 0, 0x14)]), &preimages).unwrap();
+        assert!(mips.step().is_err());
+        assert_eq!(mips.state.registers[10], 0);
+    }
+
+    #[test]
+    fn test_mmap() {
+        let mut program = vec![
+            // $a0 = 0, $a1 = 1: the heap grows by a page
+            i_type(9, 0, 5, 1),
+        ];
+        program.extend(syscall(SYS_MMAP));
+        // $s0 = $v0
+        program.push(r_type(2, 0, 16, 0, 0x21));
+        // $a1 = 0x7fff_0000: past the heap end
+        program.push(i_type(0xf, 0, 5, 0x7fff));
+        program.extend(syscall(SYS_MMAP));
+        // $s1 = $v0, $s2 = $a3
+        program.push(r_type(2, 0, 17, 0, 0x21));
+        program.push(r_type(7, 0, 18, 0, 0x21));
+        // $a1 = -1: overflows as it's rounded up
+        program.push(i_type(9, 0, 5, 0xffff));
+        program.extend(syscall(SYS_MMAP));
+        program.extend(syscall(SYS_EXIT_GROUP));
+
+        let preimages = preimages(B256::ZERO, b"");
+        let mut mips = Mips32::new(state(&program), &preimages).unwrap();
+        mips.run(None).unwrap();
+        assert_eq!(mips.state.registers[16], 0x0500_0000);
+        assert_eq!(mips.state.registers[17], u32::MAX);
+        assert_eq!(mips.state.registers[18], EINVAL);
+        assert_eq!(mips.state.registers[7], EINVAL);
+        assert_eq!(mips.state.heap, 0x0500_1000);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut program = vec![
+            // $t0 = 6, $t1 = -7
+            i_type(9, 0, 8, 6),
+            i_type(9, 0, 9, 0xfff9),
+            // lo = $t0 * $t1, $t2 = lo
+            r_type(8, 9, 0, 0, 0x18),
+            r_type(0, 0, 10, 0, 0x12),
+            // if $t2 < 0, skip the next instruction, with a nop in the delay slot
+            i_type(1, 10, 0, 2),
+            0,
+            // $t2 = 0, skipped
+            r_type(0, 0, 10, 0, 0x25),
+            // sw $t2, 0x100($zero); lbu $t3, 0x103($zero)
+            i_type(0x2b, 0, 10, 0x100),
+            i_type(0x24, 0, 11, 0x103),
+            // $a0 = $t3
+            r_type(11, 0, 4, 0, 0x21),
+        ];
+        program.extend(syscall(SYS_EXIT_GROUP));
+
+        let preimages = preimages(B256::ZERO, b"");
+        let mut mips = Mips32::new(state(&program), &preimages).unwrap();
+        mips.run(None).unwrap();
+        assert!(mips.state.exited);
+        assert_eq!(mips.state.exit_code, 0xd6);
+        assert_eq!(mips.state.registers[10], (-42i32) as u32);
+        assert_eq!(mips.state.step, program.len() as u64 - 1);
+        // Stepping an exited state does nothing.
+        mips.step().unwrap();
+        assert_eq!(mips.state.step, program.len() as u64 - 1);
+    }
+
+    #[test]
+    fn test_preimage_syscalls() {
+        let key: B256 = PreimageKey::keccak256(b"hello").into();
+        let mut program = vec![
+            // $a0 = 6, $a1 = 0x100, $a2 = 4: write the key, a word at a time
+            i_type(9, 0, 4, FD_PREIMAGE_WRITE as u16),
+            i_type(9, 0, 5, 0x100),
+            i_type(9, 0, 6, 4),
+        ];
+        for _ in 0..8 {
+            program.extend(syscall(SYS_WRITE));
+            program.push(i_type(9, 5, 5, 4));
+        }
+        // $a0 = 5, $a2 = 4: read 3 bytes into the unaligned word at 0x201, then two full words
+        program.push(i_type(9, 0, 4, FD_PREIMAGE_READ as u16));
+        for address in [0x201, 0x204, 0x208] {
+            program.push(i_type(9, 0, 5, address));
+            program.extend(syscall(SYS_READ));
+        }
+        // $s0 = $v0
+        program.push(r_type(2, 0, 16, 0, 0x21));
+        program.extend(syscall(SYS_EXIT_GROUP));
+
+        let mut state = state(&program);
+        let FPVMState::SingleThreaded(inner) = &mut state.state else {
+            unreachable!()
+        };
+        inner.memory.set_range(0x100, key.as_slice());
+        inner.memory.set_range(0x200, &[0xff; 12]);
+        let preimages = preimages(key, b"hello");
+        let mut mips = Mips32::new(state.clone(), &preimages).unwrap();
+        mips.run(None).unwrap();
+
+        assert_eq!(mips.state.preimage_key, key);
+        assert_eq!(mips.state.registers[16], 4);
+        assert_eq!(mips.state.perimage_offset, 11);
+        // The 8-byte length prefix, followed by the preimage.
+        assert_eq!(mips.load_range(0x200, 12), b"\xff\0\0\0\0\0\0\0\x05hel");
+        assert_eq!(preimages.num_requests(), 1);
+
+        // Stopping at a step leaves the state just before it.
+        let mut mips = Mips32::new(state, &preimages).unwrap();
+        mips.run(Some(3)).unwrap();
+        assert_eq!((mips.state.step, mips.state.cpu.pc), (3, 12));
+    }
+}
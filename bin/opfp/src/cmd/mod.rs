@@ -4,8 +4,22 @@ use clap::Parser;
 use color_eyre::eyre::{eyre, Result};
 use tracing::Level;
 
+pub mod baseline;
+pub mod benchmark;
+pub mod bisect;
 pub mod from_op_program;
+pub mod load_elf;
+pub mod migrate;
+pub mod minimize;
+pub mod mips;
+pub mod preimage;
+pub mod progress;
+pub mod report;
 pub mod run_op_program;
+pub mod run_suite;
+pub mod serve_preimages;
+pub mod state;
+pub mod triage;
 pub mod util;
 
 /// Main CLI
@@ -24,6 +38,20 @@ pub enum Commands {
     FromOpProgram(from_op_program::FromOpProgram),
     /// Runs the op-program implementation with a given fixture.
     RunOpProgram(run_op_program::RunOpProgram),
+    /// Runs every fixture in a directory through the op-program implementation.
+    RunSuite(run_suite::RunSuite),
+    /// Serves a fixture's witness data as the preimage oracle host of cannon.
+    ServePreimages(serve_preimages::ServePreimages),
+    /// Prunes a fixture's witness data down to the preimages the program requests.
+    Minimize(minimize::Minimize),
+    /// Rewrites fixtures in place in the current fixture format.
+    Migrate(migrate::Migrate),
+    /// Inspects and compares cannon states.
+    State(state::State),
+    /// Builds a cannon prestate and its metadata from the op-program client ELF.
+    LoadElf(load_elf::LoadElf),
+    /// Simulates the dispute game bisection over a fixture run with a wrong claim.
+    Bisect(bisect::Bisect),
 }
 
 impl Cli {
@@ -32,6 +60,13 @@ impl Cli {
         match &self.command {
             Commands::FromOpProgram(cmd) => cmd.v,
             Commands::RunOpProgram(cmd) => cmd.v,
+            Commands::RunSuite(cmd) => cmd.v,
+            Commands::ServePreimages(cmd) => cmd.v,
+            Commands::Minimize(cmd) => cmd.v,
+            Commands::Migrate(cmd) => cmd.v,
+            Commands::State(cmd) => cmd.v(),
+            Commands::LoadElf(cmd) => cmd.v,
+            Commands::Bisect(cmd) => cmd.v,
         }
     }
 
@@ -56,6 +91,13 @@ impl Cli {
         match self.command {
             Commands::FromOpProgram(cmd) => cmd.run().await,
             Commands::RunOpProgram(cmd) => cmd.run().await,
+            Commands::RunSuite(cmd) => cmd.run().await,
+            Commands::ServePreimages(cmd) => cmd.run().await,
+            Commands::Minimize(cmd) => cmd.run().await,
+            Commands::Migrate(cmd) => cmd.run().await,
+            Commands::State(cmd) => cmd.run().await,
+            Commands::LoadElf(cmd) => cmd.run().await,
+            Commands::Bisect(cmd) => cmd.run().await,
         }
     }
 }
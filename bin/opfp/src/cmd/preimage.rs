@@ -0,0 +1,436 @@
+//! A native host for the fault proof program's preimage oracle.
+//!
+//! The client and the host communicate over two pairs of pipes. From the perspective of either
+//! side, fds 3 and 4 are used to read and write hints, and fds 5 and 6 are used to read and write
+//! preimages.
+
+use alloy_primitives::{hex::ToHexExt, Bytes, B256};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use fp_test_fixtures::migration::registry_chain_id;
+use fp_test_fixtures::{ChainDefinition, FaultProofFixture, PreimageKey};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+use tracing::{error, trace};
+
+use super::util::RollupConfig;
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "preimage";
+
+/// The fd the client reads hint acknowledgements from.
+const HINT_CLIENT_READ_FD: i32 = 3;
+/// The fd the client writes hints to.
+const HINT_CLIENT_WRITE_FD: i32 = 4;
+/// The fd the client reads preimages from.
+const PREIMAGE_CLIENT_READ_FD: i32 = 5;
+/// The fd the client writes preimage keys to.
+const PREIMAGE_CLIENT_WRITE_FD: i32 = 6;
+
+/// The local key indices of the program's boot information.
+const L1_HEAD_LOCAL_INDEX: u64 = 1;
+const L2_OUTPUT_ROOT_LOCAL_INDEX: u64 = 2;
+const L2_CLAIM_LOCAL_INDEX: u64 = 3;
+const L2_CLAIM_BLOCK_NUMBER_LOCAL_INDEX: u64 = 4;
+const L2_CHAIN_ID_LOCAL_INDEX: u64 = 5;
+const L2_CHAIN_CONFIG_LOCAL_INDEX: u64 = 6;
+const ROLLUP_CONFIG_LOCAL_INDEX: u64 = 7;
+
+/// The chain ID which tells the program to load the chain configuration from local keys.
+const CUSTOM_CHAIN_ID_INDICATOR: u64 = u64::MAX;
+
+/// Serves preimages from a fixture's witness data over the preimage oracle protocol.
+#[derive(Debug)]
+pub struct PreimageServer {
+    /// The preimages, including the local boot information.
+    preimages: BTreeMap<B256, Bytes>,
+    /// The keys the client has requested.
+    accessed: Mutex<BTreeSet<B256>>,
+    /// An optional log that every newly requested key is appended to.
+    log: Mutex<Option<BufWriter<File>>>,
+    /// The number of preimage requests.
+    num_requests: AtomicU64,
+    /// The total size of the served preimages.
+    total_size: AtomicU64,
+}
+
+impl PreimageServer {
+    /// Creates a new [PreimageServer] for the given fixture.
+    pub fn new(fixture: &FaultProofFixture) -> Result<Self> {
+        let mut preimages = fixture.witness_data.clone();
+        preimages.extend(local_preimages(fixture)?);
+
+        Ok(Self {
+            preimages,
+            accessed: Default::default(),
+            log: Default::default(),
+            num_requests: Default::default(),
+            total_size: Default::default(),
+        })
+    }
+
+    /// Appends every newly requested key to the given file, one hex key per line.
+    ///
+    /// Each key is flushed as soon as it is requested, so the log is complete even if the server
+    /// is killed.
+    pub fn with_log(self, path: &Path) -> Result<Self> {
+        let file = File::create(path)?;
+        *self.log.lock().unwrap() = Some(BufWriter::new(file));
+        Ok(self)
+    }
+
+    /// Returns the preimage for the given key, recording the request.
+    pub fn get(&self, key: B256) -> Result<&Bytes> {
+        let preimage = self
+            .preimages
+            .get(&key)
+            .ok_or_else(|| eyre!("Missing preimage for key {}", key))?;
+
+        self.num_requests.fetch_add(1, Ordering::Relaxed);
+        self.total_size
+            .fetch_add(preimage.len() as u64, Ordering::Relaxed);
+        if self.accessed.lock().unwrap().insert(key) {
+            if let Some(log) = self.log.lock().unwrap().as_mut() {
+                writeln!(log, "{}", key.encode_hex_with_prefix())?;
+                log.flush()?;
+            }
+        }
+
+        Ok(preimage)
+    }
+
+    /// Returns the keys the client has requested so far.
+    pub fn accessed_keys(&self) -> BTreeSet<B256> {
+        self.accessed.lock().unwrap().clone()
+    }
+
+    /// Returns the number of preimage requests served so far.
+    pub fn num_requests(&self) -> u64 {
+        self.num_requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total size of the preimages served so far.
+    pub fn total_size(&self) -> u64 {
+        self.total_size.load(Ordering::Relaxed)
+    }
+
+    /// Serves preimage requests until the client closes the channel.
+    ///
+    /// Each request is a 32 byte key, and each response is the 8 byte big-endian length of the
+    /// preimage followed by the preimage itself.
+    pub fn serve_preimages(&self, reader: impl Read, writer: impl Write) -> Result<()> {
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+
+        let mut key = [0u8; 32];
+        while read_message(&mut reader, &mut key)? {
+            let key = B256::from(key);
+            trace!(target: TARGET, "Preimage request: {}", key);
+
+            let preimage = self
+                .get(key)
+                .inspect_err(|e| error!(target: TARGET, "{}", e))?;
+            writer.write_all(&(preimage.len() as u64).to_be_bytes())?;
+            writer.write_all(preimage)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Acknowledges hints until the client closes the channel.
+    ///
+    /// Each hint is prefixed with its 4 byte big-endian length, and is acknowledged with a single
+    /// byte. All preimages are known up front, so the hints are only logged.
+    pub fn serve_hints(&self, reader: impl Read, mut writer: impl Write) -> Result<()> {
+        let mut reader = BufReader::new(reader);
+
+        let mut len = [0u8; 4];
+        while read_message(&mut reader, &mut len)? {
+            let mut hint = vec![0u8; u32::from_be_bytes(len) as usize];
+            reader.read_exact(&mut hint)?;
+            trace!(target: TARGET, "Hint: {}", String::from_utf8_lossy(&hint));
+
+            writer.write_all(&[0])?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Serves hints and preimages on separate threads until the client closes both channels.
+    pub fn serve(
+        self: &Arc<Self>,
+        hint_reader: File,
+        hint_writer: File,
+        preimage_reader: File,
+        preimage_writer: File,
+    ) -> Result<()> {
+        let server = self.clone();
+        let hints = std::thread::spawn(move || server.serve_hints(hint_reader, hint_writer));
+        let server = self.clone();
+        let preimages =
+            std::thread::spawn(move || server.serve_preimages(preimage_reader, preimage_writer));
+
+        let hints = hints.join().map_err(|_| eyre!("Hint server panicked"))?;
+        let preimages = preimages
+            .join()
+            .map_err(|_| eyre!("Preimage server panicked"))?;
+        hints.and(preimages)
+    }
+
+    /// Serves the host side of the channels on fds 3 to 6 of the current process.
+    ///
+    /// This is how cannon connects to the host process it runs.
+    pub fn serve_stdio_fds(self: &Arc<Self>) -> Result<()> {
+        // SAFETY: the host is started with the channel fds open, and they are not used elsewhere.
+        let (hint_reader, hint_writer, preimage_reader, preimage_writer) = unsafe {
+            (
+                File::from_raw_fd(HINT_CLIENT_READ_FD),
+                File::from_raw_fd(HINT_CLIENT_WRITE_FD),
+                File::from_raw_fd(PREIMAGE_CLIENT_READ_FD),
+                File::from_raw_fd(PREIMAGE_CLIENT_WRITE_FD),
+            )
+        };
+        self.serve(hint_reader, hint_writer, preimage_reader, preimage_writer)
+    }
+
+    /// Spawns the client command with the channels on fds 3 to 6, and serves it until it exits.
+    pub async fn run_client(self: &Arc<Self>, mut command: Command) -> Result<ExitStatus> {
+        let (client_hint_reader, host_hint_writer) = io::pipe()?;
+        let (host_hint_reader, client_hint_writer) = io::pipe()?;
+        let (client_preimage_reader, host_preimage_writer) = io::pipe()?;
+        let (host_preimage_reader, client_preimage_writer) = io::pipe()?;
+
+        let client_fds: [OwnedFd; 4] = [
+            client_hint_reader.into(),
+            client_hint_writer.into(),
+            client_preimage_reader.into(),
+            client_preimage_writer.into(),
+        ];
+        let sources = client_fds.each_ref().map(|fd| fd.as_raw_fd());
+
+        // SAFETY: only async-signal-safe functions are called between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                // Move the fds out of the way first, so that none of them is overwritten before
+                // it has been duplicated onto its target.
+                let mut moved = [0; 4];
+                for (moved, source) in moved.iter_mut().zip(sources) {
+                    *moved = libc::fcntl(source, libc::F_DUPFD_CLOEXEC, 10);
+                    if *moved < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                let targets = [
+                    HINT_CLIENT_READ_FD,
+                    HINT_CLIENT_WRITE_FD,
+                    PREIMAGE_CLIENT_READ_FD,
+                    PREIMAGE_CLIENT_WRITE_FD,
+                ];
+                for (moved, target) in moved.into_iter().zip(targets) {
+                    if libc::dup2(moved, target) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| eyre!("Failed to execute client binary: {}", e))?;
+        // Close the client's ends in this process, so the server sees EOF once the client exits.
+        drop(client_fds);
+
+        let server = self.clone();
+        let serve = tokio::task::spawn_blocking(move || {
+            server.serve(
+                File::from(OwnedFd::from(host_hint_reader)),
+                File::from(OwnedFd::from(host_hint_writer)),
+                File::from(OwnedFd::from(host_preimage_reader)),
+                File::from(OwnedFd::from(host_preimage_writer)),
+            )
+        });
+
+        let status = child.wait().await?;
+        if let Err(e) = serve.await? {
+            // The client sees a closed channel and fails, so the status reflects the error.
+            error!(target: TARGET, "Preimage server failed: {}", e);
+        }
+        Ok(status)
+    }
+}
+
+/// Reads the preimage keys recorded by [PreimageServer::with_log].
+pub fn read_accessed_keys(path: &Path) -> Result<BTreeSet<B256>> {
+    std::fs::read_to_string(path)
+        .map_err(|e| eyre!("Failed to read accessed keys: {}", e))?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| B256::from_str(line).map_err(|e| eyre!("Invalid key {}: {}", line, e)))
+        .collect()
+}
+
+/// Reads a fixed size message, returning false if the channel was closed before it started.
+fn read_message(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the preimage key of a local key index.
+pub fn local_key(index: u64) -> B256 {
+    PreimageKey::local(index).into()
+}
+
+/// Returns the local preimages holding the boot information of the fixture.
+fn local_preimages(fixture: &FaultProofFixture) -> Result<BTreeMap<B256, Bytes>> {
+    let inputs = &fixture.inputs;
+    let mut locals = BTreeMap::from([
+        (
+            local_key(L1_HEAD_LOCAL_INDEX),
+            Bytes::copy_from_slice(inputs.l1_head.as_slice()),
+        ),
+        (
+            local_key(L2_OUTPUT_ROOT_LOCAL_INDEX),
+            Bytes::copy_from_slice(inputs.l2_output_root.as_slice()),
+        ),
+        (
+            local_key(L2_CLAIM_LOCAL_INDEX),
+            Bytes::copy_from_slice(inputs.l2_claim.as_slice()),
+        ),
+        (
+            local_key(L2_CLAIM_BLOCK_NUMBER_LOCAL_INDEX),
+            Bytes::copy_from_slice(&inputs.l2_block_number.to_be_bytes()),
+        ),
+    ]);
+
+    match &inputs.chain_definition {
+        // op-program looks registry chains up by their chain ID, without the configs.
+        ChainDefinition::Named(name) => {
+            let chain_id = registry_chain_id(name)
+                .ok_or_else(|| eyre!("Named chain {} is not a registry network", name))?;
+            locals.insert(
+                local_key(L2_CHAIN_ID_LOCAL_INDEX),
+                Bytes::copy_from_slice(&chain_id.to_be_bytes()),
+            );
+        }
+        ChainDefinition::Unnamed(rollup_config, genesis) => {
+            let rollup_config: RollupConfig = rollup_config.into();
+            locals.insert(
+                local_key(L2_CHAIN_ID_LOCAL_INDEX),
+                Bytes::copy_from_slice(&CUSTOM_CHAIN_ID_INDICATOR.to_be_bytes()),
+            );
+            locals.insert(
+                local_key(L2_CHAIN_CONFIG_LOCAL_INDEX),
+                serde_json::to_vec(&genesis.config)?.into(),
+            );
+            locals.insert(
+                local_key(ROLLUP_CONFIG_LOCAL_INDEX),
+                serde_json::to_vec(&rollup_config)?.into(),
+            );
+        }
+    }
+
+    Ok(locals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fp_test_fixtures::Genesis;
+
+    fn server() -> PreimageServer {
+        let mut rollup_config = superchain_primitives::RollupConfig::default();
+        rollup_config.genesis.system_config = Some(Default::default());
+        let mut fixture = FaultProofFixture::default();
+        fixture.inputs.chain_definition =
+            ChainDefinition::Unnamed(rollup_config, Genesis::default());
+        fixture
+            .witness_data
+            .insert(B256::from([2; 32]), Bytes::from_static(b"hello"));
+        PreimageServer::new(&fixture).unwrap()
+    }
+
+    #[test]
+    fn test_serve_preimages() {
+        let server = server();
+        let mut request = B256::from([2; 32]).to_vec();
+        request.extend_from_slice(local_key(L2_CLAIM_BLOCK_NUMBER_LOCAL_INDEX).as_slice());
+
+        let mut response = Vec::new();
+        server
+            .serve_preimages(request.as_slice(), &mut response)
+            .unwrap();
+
+        let mut expected = 5u64.to_be_bytes().to_vec();
+        expected.extend_from_slice(b"hello");
+        expected.extend_from_slice(&8u64.to_be_bytes());
+        expected.extend_from_slice(&0u64.to_be_bytes());
+        assert_eq!(response, expected);
+        assert_eq!(server.num_requests(), 2);
+        assert_eq!(server.total_size(), 13);
+        assert_eq!(server.accessed_keys().len(), 2);
+    }
+
+    #[test]
+    fn test_named_chain() {
+        let mut fixture = FaultProofFixture::default();
+        fixture.inputs.chain_definition = ChainDefinition::Named("base-mainnet".to_string());
+        let locals = local_preimages(&fixture).unwrap();
+        assert_eq!(
+            locals[&local_key(L2_CHAIN_ID_LOCAL_INDEX)],
+            Bytes::copy_from_slice(&8453u64.to_be_bytes())
+        );
+        assert!(!locals.contains_key(&local_key(L2_CHAIN_CONFIG_LOCAL_INDEX)));
+        assert!(!locals.contains_key(&local_key(ROLLUP_CONFIG_LOCAL_INDEX)));
+
+        fixture.inputs.chain_definition = ChainDefinition::Named("devnet".to_string());
+        assert!(local_preimages(&fixture).is_err());
+    }
+
+    #[test]
+    fn test_serve_missing_preimage() {
+        let server = server();
+        let request = B256::from([3; 32]);
+        let result = server.serve_preimages(request.as_slice(), Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_accessed_keys_log() {
+        let path = std::env::temp_dir().join(format!("opfp-accessed-keys-{}", std::process::id()));
+        let server = server().with_log(&path).unwrap();
+        server.get(B256::from([2; 32])).unwrap();
+        server.get(B256::from([2; 32])).unwrap();
+        server.get(local_key(L1_HEAD_LOCAL_INDEX)).unwrap();
+
+        let keys = read_accessed_keys(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(keys, server.accessed_keys());
+        assert_eq!(keys.len(), 2);
+    }
+
+    #[test]
+    fn test_serve_hints() {
+        let server = server();
+        let mut request = 4u32.to_be_bytes().to_vec();
+        request.extend_from_slice(b"hint");
+        request.extend_from_slice(&0u32.to_be_bytes());
+
+        let mut response = Vec::new();
+        server
+            .serve_hints(request.as_slice(), &mut response)
+            .unwrap();
+        assert_eq!(response, vec![0, 0]);
+    }
+}
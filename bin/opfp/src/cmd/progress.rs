@@ -0,0 +1,217 @@
+//! Parsing of the progress cannon logs at its `--info-at` steps.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Write;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// The message of cannon's progress log lines.
+const PROGRESS_MESSAGE: &str = "processing";
+
+/// A progress sample logged by cannon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressSample {
+    /// The milliseconds since cannon was started when the sample was logged.
+    pub elapsed: u128,
+    /// The number of executed instructions.
+    pub step: u64,
+    /// The program counter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pc: Option<u64>,
+    /// The instructions per second since the start of the run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ips: Option<f64>,
+    /// The number of allocated memory pages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<u64>,
+    /// The memory used, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_used: Option<u64>,
+    /// The symbol of the program counter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+}
+
+/// Reads cannon's output line by line, collecting its progress samples.
+///
+/// Unless `quiet` is set, every line is forwarded to stdout, or stderr if `stderr` is set.
+pub async fn capture(
+    reader: impl AsyncRead + Unpin,
+    start: Instant,
+    quiet: bool,
+    stderr: bool,
+) -> std::io::Result<Vec<ProgressSample>> {
+    let mut reader = BufReader::new(reader);
+    let mut samples = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            break;
+        }
+        if !quiet {
+            // Forwarding is best effort; a closed terminal shouldn't fail the run.
+            let _ = if stderr {
+                std::io::stderr().write_all(&line)
+            } else {
+                std::io::stdout().write_all(&line)
+            };
+        }
+        if let Some(sample) =
+            parse_line(&String::from_utf8_lossy(&line), start.elapsed().as_millis())
+        {
+            samples.push(sample);
+        }
+    }
+    Ok(samples)
+}
+
+/// Parses a progress log line in the JSON or the `key=value` log format, or returns `None` if
+/// the line isn't one.
+pub fn parse_line(line: &str, elapsed: u128) -> Option<ProgressSample> {
+    let line = line.trim();
+    let fields: Vec<(String, String)> = if line.starts_with('{') {
+        let Ok(Value::Object(object)) = serde_json::from_str::<Value>(line) else {
+            return None;
+        };
+        if object.get("msg").and_then(Value::as_str) != Some(PROGRESS_MESSAGE) {
+            return None;
+        }
+        object
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::String(value) => (key, value),
+                value => (key, value.to_string()),
+            })
+            .collect()
+    } else {
+        // The terminal format logs the bare message, logfmt logs it as a field.
+        let (_, fields) = line
+            .split_once(&format!(" {} ", PROGRESS_MESSAGE))
+            .or_else(|| line.split_once(&format!("msg={} ", PROGRESS_MESSAGE)))?;
+        parse_key_values(fields)
+    };
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    Some(ProgressSample {
+        elapsed,
+        step: parse_number(field("step")?)?,
+        pc: field("pc").and_then(parse_hex),
+        ips: field("ips").and_then(|ips| ips.replace(',', "").parse().ok()),
+        pages: field("pages").and_then(parse_number),
+        memory_used: field("mem").and_then(parse_memory),
+        symbol: field("name").map(str::to_string),
+    })
+}
+
+/// Splits `key=value` pairs, where values may be quoted.
+fn parse_key_values(fields: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = fields.trim_start();
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, remainder)) => (value, remainder),
+                None => (quoted, ""),
+            },
+            None => value.split_once(' ').unwrap_or((value, "")),
+        };
+        pairs.push((key.trim().to_string(), value.to_string()));
+        rest = remainder.trim_start();
+    }
+    pairs
+}
+
+/// Parses a number that may contain thousands separators.
+fn parse_number(value: &str) -> Option<u64> {
+    value.replace(',', "").parse().ok()
+}
+
+/// Parses a hex number with an optional `0x` prefix.
+fn parse_hex(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+/// Parses a memory size as formatted by cannon, e.g. `512 B` or `4.5 MiB`.
+fn parse_memory(value: &str) -> Option<u64> {
+    let (amount, unit) = value.trim().split_once(' ')?;
+    let amount: f64 = amount.parse().ok()?;
+    let exponent = match unit {
+        "B" => 0,
+        "KiB" => 1,
+        "MiB" => 2,
+        "GiB" => 3,
+        "TiB" => 4,
+        _ => return None,
+    };
+    Some((amount * 1024f64.powi(exponent)) as u64)
+}
+
+/// Returns the pair of consecutive samples with the lowest step rate between them.
+pub fn slowest_interval(samples: &[ProgressSample]) -> Option<(&ProgressSample, &ProgressSample)> {
+    let rate = |(a, b): &(&ProgressSample, &ProgressSample)| {
+        let steps = b.step.saturating_sub(a.step) as f64;
+        let millis = b.elapsed.saturating_sub(a.elapsed).max(1) as f64;
+        steps / millis
+    };
+    samples
+        .iter()
+        .zip(samples.iter().skip(1))
+        .min_by(|a, b| rate(a).total_cmp(&rate(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let json = r#"{"t":"2024-10-18T12:00:00Z","lvl":"info","msg":"processing","step":20000000,"pc":"0x0010a5c4","insn":"0x8fbf0014","ips":1.25e7,"pages":1234,"mem":"4.8 MiB","name":"runtime.mallocgc"}"#;
+        let terminal = r#"INFO [10-18|12:00:00.000] processing                               step=20,000,000 pc=0010a5c4 insn=8fbf0014 ips=1.25e+07 pages=1234 mem="4.8 MiB" name=runtime.mallocgc"#;
+        let expected = ProgressSample {
+            elapsed: 1500,
+            step: 20_000_000,
+            pc: Some(0x0010a5c4),
+            ips: Some(1.25e7),
+            pages: Some(1234),
+            memory_used: Some(5033164),
+            symbol: Some("runtime.mallocgc".to_string()),
+        };
+        assert_eq!(parse_line(json, 1500), Some(expected.clone()));
+        assert_eq!(parse_line(terminal, 1500), Some(expected));
+        let logfmt = r#"t=2024-10-18T12:00:00+0000 lvl=info msg=processing step=20000000 pc=0x0010a5c4 ips=12500000 pages=1234 mem="4.8 MiB" name=runtime.mallocgc"#;
+        assert_eq!(parse_line(logfmt, 0).unwrap().step, 20_000_000);
+
+        assert_eq!(parse_line(r#"{"msg":"loaded","step":1}"#, 0), None);
+        assert_eq!(parse_line("t=... lvl=info msg=\"Starting\"", 0), None);
+        assert_eq!(parse_memory("512 B"), Some(512));
+    }
+
+    #[test]
+    fn test_slowest_interval() {
+        let sample = |elapsed, step| ProgressSample {
+            elapsed,
+            step,
+            pc: None,
+            ips: None,
+            pages: None,
+            memory_used: None,
+            symbol: None,
+        };
+        let samples = [
+            sample(0, 0),
+            sample(100, 1000),
+            sample(1100, 2000),
+            sample(1200, 3000),
+        ];
+        let (from, to) = slowest_interval(&samples).unwrap();
+        assert_eq!((from.step, to.step), (1000, 2000));
+        assert_eq!(slowest_interval(&samples[..1]), None);
+    }
+}
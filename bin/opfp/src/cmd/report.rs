@@ -0,0 +1,327 @@
+//! Machine-readable reports for fixture runs.
+
+use clap::Args;
+use color_eyre::Result;
+use fp_test_fixtures::encoding::fixture_stem;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use super::run_op_program::FixtureOutcome;
+
+/// CLI arguments for writing test reports.
+#[derive(Args, Clone, Debug, Default)]
+pub struct ReportArgs {
+    /// Optional JUnit XML report file path
+    #[clap(long, help = "Path to write a JUnit XML report to")]
+    pub junit: Option<PathBuf>,
+    /// Optional TAP report file path
+    #[clap(long, help = "Path to write a TAP report to")]
+    pub tap: Option<PathBuf>,
+}
+
+impl ReportArgs {
+    /// Writes the configured reports for the given outcomes.
+    pub fn write(&self, suite: &str, outcomes: &[FixtureOutcome]) -> Result<()> {
+        if let Some(path) = &self.junit {
+            std::fs::write(path, junit(suite, outcomes))?;
+        }
+        if let Some(path) = &self.tap {
+            std::fs::write(path, tap(outcomes))?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders the outcomes as a JUnit XML document with one test case per fixture.
+pub fn junit(suite: &str, outcomes: &[FixtureOutcome]) -> String {
+    // A runner error means the fixture couldn't be run at all, which JUnit tells apart from a
+    // failed check.
+    let errors = outcomes.iter().filter(|o| o.error.is_some()).count();
+    let failures = outcomes.iter().filter(|o| !o.passed()).count() - errors;
+    let total_ms: u128 = outcomes
+        .iter()
+        .filter_map(|o| o.stats.as_ref().map(|stats| stats.runtime))
+        .sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">",
+        outcomes.len(),
+        failures,
+        errors,
+        seconds(total_ms)
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">",
+        escape(suite),
+        outcomes.len(),
+        failures,
+        errors,
+        seconds(total_ms)
+    );
+
+    for outcome in outcomes {
+        let runtime = outcome.stats.as_ref().map(|stats| stats.runtime);
+        let _ = writeln!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\">",
+            escape(&test_name(&outcome.fixture)),
+            escape(suite),
+            seconds(runtime.unwrap_or_default())
+        );
+
+        xml.push_str("      <properties>\n");
+        for (name, value) in properties(outcome) {
+            let _ = writeln!(
+                xml,
+                "        <property name=\"{}\" value=\"{}\"/>",
+                name,
+                escape(&value)
+            );
+        }
+        xml.push_str("      </properties>\n");
+
+        if let Some(failure) = outcome.failure() {
+            let element = if outcome.error.is_some() {
+                "error"
+            } else {
+                "failure"
+            };
+            let _ = writeln!(
+                xml,
+                "      <{} message=\"{}\">{}</{}>",
+                element,
+                escape(&failure),
+                escape(&failure),
+                element
+            );
+        }
+        xml.push_str("    </testcase>\n");
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Renders the outcomes as a TAP version 13 document with one test point per fixture.
+pub fn tap(outcomes: &[FixtureOutcome]) -> String {
+    let mut out = format!("TAP version 13\n1..{}\n", outcomes.len());
+
+    for (i, outcome) in outcomes.iter().enumerate() {
+        let failure = outcome.failure();
+        let _ = writeln!(
+            out,
+            "{} {} - {}",
+            if failure.is_none() { "ok" } else { "not ok" },
+            i + 1,
+            test_name(&outcome.fixture)
+        );
+
+        // YAML diagnostics block.
+        out.push_str("  ---\n");
+        if let Some(failure) = failure {
+            let _ = writeln!(out, "  message: {}", yaml_string(&failure));
+        }
+        for (name, value) in properties(outcome) {
+            let _ = writeln!(out, "  {}: {}", name, yaml_string(&value));
+        }
+        out.push_str("  ...\n");
+    }
+
+    out
+}
+
+/// Returns the name of the test case for a fixture file.
+fn test_name(fixture: &Path) -> String {
+    fixture_stem(fixture).unwrap_or_else(|| fixture.to_string_lossy().into_owned())
+}
+
+/// Returns the reported properties of an outcome.
+fn properties(outcome: &FixtureOutcome) -> Vec<(&'static str, String)> {
+    let mut properties = vec![("fixture", outcome.fixture.display().to_string())];
+    if let Some(expected) = outcome.expected_status {
+        properties.push(("expected_status", format!("{:?}", expected)));
+    }
+    if let Some(stats) = &outcome.stats {
+        if let Some(status) = stats.status {
+            properties.push(("status", format!("{:?}", status)));
+        }
+        properties.push(("runtime_ms", stats.runtime.to_string()));
+        if let Some(instructions) = stats.instructions {
+            properties.push(("instructions", instructions.to_string()));
+        }
+        if let Some(memory_used) = stats.memory_used {
+            properties.push(("memory_used", memory_used.to_string()));
+        }
+        if let Some(requests) = stats.num_preimage_requests {
+            properties.push(("num_preimage_requests", requests.to_string()));
+        }
+        if let Some(size) = stats.total_preimage_size {
+            properties.push(("total_preimage_size", size.to_string()));
+        }
+    }
+    properties
+}
+
+/// Formats a millisecond duration as seconds.
+fn seconds(ms: u128) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+/// Formats a string as a double-quoted YAML scalar.
+fn yaml_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escapes a string for use in XML attributes and text, replacing the characters XML 1.0
+/// forbids, such as the escape codes of colored output.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\0'..='\x1f' | '\u{fffe}' | '\u{ffff}' => escaped.push(char::REPLACEMENT_CHARACTER),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::run_op_program::ProgramStats;
+    use fp_test_fixtures::{FaultProofStatus, ResourceBudgets};
+
+    fn outcomes() -> Vec<FixtureOutcome> {
+        vec![
+            FixtureOutcome {
+                fixture: PathBuf::from("fixtures/Writer-1000000.json"),
+                expected_status: Some(FaultProofStatus::Valid),
+                stats: Some(ProgramStats {
+                    runtime: 1500,
+                    instructions: Some(42),
+                    status: Some(FaultProofStatus::Valid),
+                    ..Default::default()
+                }),
+                error: None,
+                budgets: None,
+                benchmark: None,
+            },
+            FixtureOutcome {
+                fixture: PathBuf::from("fixtures/Reader-2000000.json"),
+                expected_status: Some(FaultProofStatus::Valid),
+                stats: Some(ProgramStats {
+                    runtime: 20,
+                    status: Some(FaultProofStatus::Invalid),
+                    ..Default::default()
+                }),
+                error: None,
+                budgets: None,
+                benchmark: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let xml = junit("opfp", &outcomes());
+        assert!(xml.contains(
+            "<testsuite name=\"opfp\" tests=\"2\" failures=\"1\" errors=\"0\" time=\"1.520\">"
+        ));
+        assert!(
+            xml.contains("<testcase name=\"Writer-1000000\" classname=\"opfp\" time=\"1.500\">")
+        );
+        assert!(xml.contains("<property name=\"instructions\" value=\"42\"/>"));
+        assert!(xml.contains("<failure message=\"expected status Valid, got Invalid\">"));
+        assert_eq!(xml.matches("<failure").count(), 1);
+    }
+
+    #[test]
+    fn test_tap_report() {
+        let tap = tap(&outcomes());
+        let lines: Vec<&str> = tap.lines().collect();
+        assert_eq!(lines[0], "TAP version 13");
+        assert_eq!(lines[1], "1..2");
+        assert!(tap.contains("ok 1 - Writer-1000000\n"));
+        assert!(tap.contains("not ok 2 - Reader-2000000\n"));
+        assert!(tap.contains("  message: \"expected status Valid, got Invalid\"\n"));
+    }
+
+    #[test]
+    fn test_budget_failure() {
+        let mut outcome = outcomes().remove(0);
+        outcome.budgets = Some(ResourceBudgets {
+            max_steps: Some(40),
+            ..Default::default()
+        });
+
+        let xml = junit("opfp", std::slice::from_ref(&outcome));
+        assert!(xml.contains("<failure message=\"steps 42 exceeds the budget of 40\">"));
+    }
+
+    #[test]
+    fn test_error_report() {
+        let mut outcome = outcomes().remove(0);
+        outcome.stats = None;
+        outcome.error = Some("Failed to read fixture file: \"x\"\nnot found".to_string());
+
+        let xml = junit("opfp", std::slice::from_ref(&outcome));
+        assert!(xml.contains("tests=\"1\" failures=\"0\" errors=\"1\""));
+        assert!(xml.contains("<error message=\"Failed to read fixture file: &quot;x&quot;"));
+        assert_eq!(xml.matches("<failure").count(), 0);
+
+        let tap = tap(std::slice::from_ref(&outcome));
+        assert!(tap.contains("  message: \"Failed to read fixture file: \\\"x\\\"\\nnot found\"\n"));
+    }
+
+    #[test]
+    fn test_yaml_string() {
+        assert_eq!(yaml_string("plain"), "\"plain\"");
+        assert_eq!(
+            yaml_string("say \"hi\"\\\n\u{1b}"),
+            "\"say \\\"hi\\\"\\\\\\n\\u001b\""
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+        assert_eq!(
+            escape("\x1b[31merror\x1b[0m:\tline\n"),
+            "\u{fffd}[31merror\u{fffd}[0m:\tline\n"
+        );
+    }
+
+    #[test]
+    fn test_test_name() {
+        assert_eq!(
+            test_name(Path::new("fixtures/Writer-1.json.zst")),
+            "Writer-1"
+        );
+        assert_eq!(test_name(Path::new("fixtures/Reader-2.cbor")), "Reader-2");
+    }
+}
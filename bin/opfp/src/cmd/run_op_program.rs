@@ -1,18 +1,27 @@
 //! Run Op Program Subcommand
 
 use alloy_primitives::hex::ToHexExt;
-use alloy_primitives::U64;
-use clap::{ArgAction, Parser};
+use alloy_primitives::{B256, U64};
+use clap::{ArgAction, Args, Parser};
 use color_eyre::eyre::eyre;
 use color_eyre::Result;
-use fp_test_fixtures::{ChainDefinition, FaultProofFixture};
+use fp_test_fixtures::encoding::fixture_stem;
+use fp_test_fixtures::{ChainDefinition, FaultProofFixture, FaultProofStatus, ResourceBudgets};
 use serde::{Deserialize, Serialize};
-use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{env, path::PathBuf};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use tokio::process::Command;
 use tracing::{debug, error, info, trace, warn};
 
-use super::util::{RollupConfig, VersionedState};
+use super::baseline::BaselineArgs;
+use super::benchmark::Benchmark;
+use super::preimage::{read_accessed_keys, PreimageServer};
+use super::progress::{self, ProgressSample};
+use super::report::ReportArgs;
+use super::triage::{CannonMetadata, TriageReport};
+use super::util::{unique_data_dir, RollupConfig, VersionedState};
 
 /// The logging target to use for [tracing].
 const TARGET: &str = "run-op-program";
@@ -26,6 +35,42 @@ pub struct RunOpProgram {
     /// Path to the fixture file
     #[clap(short, long, help = "Path to the fixture file")]
     pub fixture: PathBuf,
+    /// Optional cannon configuration
+    #[command(flatten)]
+    pub cannon: CannonArgs,
+    /// Native preimage host configuration
+    #[command(flatten)]
+    pub host: HostArgs,
+    /// Optional output file path
+    #[clap(long, help = "Path to the output file")]
+    pub output: Option<PathBuf>,
+    /// The number of times to run the fixture
+    #[clap(
+        long,
+        default_value_t = 1,
+        help = "Run the fixture N times and report runtime statistics"
+    )]
+    pub repeat: usize,
+    /// Whether to check that repeated cannon runs end in the same final state
+    #[clap(
+        long,
+        help = "Run the fixture in cannon at least twice and fail if the final states differ"
+    )]
+    pub check_determinism: bool,
+    /// Optional test reports
+    #[command(flatten)]
+    pub reports: ReportArgs,
+    /// Optional baseline to compare the stats against
+    #[command(flatten)]
+    pub baseline: BaselineArgs,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+/// CLI arguments for running fixtures within cannon.
+#[derive(Args, Clone, Debug, Default)]
+pub struct CannonArgs {
     /// Optional path to the cannon binary
     #[clap(short, long, help = "Path to the cannon binary")]
     pub cannon: Option<PathBuf>,
@@ -35,12 +80,106 @@ pub struct RunOpProgram {
     /// Optional cannon metadata
     #[clap(long, help = "Path to the cannon metadata")]
     pub cannon_meta: Option<PathBuf>,
-    /// Optional output file path
-    #[clap(long, help = "Path to the output file")]
-    pub output: Option<PathBuf>,
-    /// Verbosity level (0-4)
-    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
-    pub v: u8,
+    /// Optional interval at which cannon logs its progress
+    #[clap(
+        long,
+        value_parser = parse_step_pattern,
+        help = "Step pattern at which cannon logs its progress (default: %10000000)"
+    )]
+    pub cannon_info_at: Option<String>,
+    /// Optional step at which cannon stops
+    #[clap(
+        long,
+        value_parser = parse_step_pattern,
+        help = "Step pattern at which cannon stops, e.g. =1000000"
+    )]
+    pub cannon_stop_at: Option<String>,
+    /// Optional steps at which cannon writes a proof
+    #[clap(
+        long,
+        value_parser = parse_step_pattern,
+        help = "Step pattern at which cannon writes a proof to the artifacts directory"
+    )]
+    pub cannon_proof_at: Option<String>,
+    /// Optional steps at which cannon writes a snapshot of its state
+    #[clap(
+        long,
+        value_parser = parse_step_pattern,
+        help = "Step pattern at which cannon writes a state snapshot to the artifacts directory"
+    )]
+    pub cannon_snapshot_at: Option<String>,
+    /// Optional preimage key at which cannon stops
+    #[clap(
+        long,
+        help = "Stop cannon at the first request of the preimage with this key"
+    )]
+    pub cannon_stop_at_preimage: Option<String>,
+    /// Optional directory to keep proofs and snapshots in
+    #[clap(
+        long,
+        help = "Directory to keep cannon proofs and snapshots in, one subdirectory per fixture (default: cannon-artifacts)"
+    )]
+    pub cannon_artifacts: Option<PathBuf>,
+}
+
+impl CannonArgs {
+    /// Returns the directory to keep the proofs and snapshots of a fixture in, or `None` if cannon
+    /// doesn't write any.
+    pub fn artifacts_dir(&self, fixture: Option<&Path>) -> Option<PathBuf> {
+        if self.cannon_proof_at.is_none() && self.cannon_snapshot_at.is_none() {
+            return None;
+        }
+        let dir = self
+            .cannon_artifacts
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("cannon-artifacts"));
+        match fixture.and_then(fixture_stem) {
+            Some(name) => Some(dir.join(name)),
+            None => Some(dir),
+        }
+    }
+}
+
+/// Parses one of cannon's step patterns: `never`, `always`, `=<step>` or `%<interval>`.
+pub fn parse_step_pattern(pattern: &str) -> Result<String, String> {
+    let valid = match pattern {
+        "never" | "always" => true,
+        _ => match pattern
+            .strip_prefix('=')
+            .or_else(|| pattern.strip_prefix('%'))
+        {
+            Some(step) => step.parse::<u64>().is_ok_and(|step| {
+                // An interval of zero would divide by zero.
+                step > 0 || pattern.starts_with('=')
+            }),
+            None => false,
+        },
+    };
+    if valid {
+        Ok(pattern.to_string())
+    } else {
+        Err(format!(
+            "invalid step pattern {:?}, expected never, always, =<step> or %<interval>",
+            pattern
+        ))
+    }
+}
+
+/// CLI arguments for serving preimages from the fixture with the native host.
+#[derive(Args, Clone, Debug, Default)]
+pub struct HostArgs {
+    /// Whether to serve preimages from memory instead of op-program's directory data format
+    #[clap(
+        long,
+        help = "Serve the witness data with the native preimage host instead of writing it to disk"
+    )]
+    pub native_host: bool,
+    /// Optional path to the op-program client binary
+    #[clap(
+        long,
+        help = "Path to the op-program client binary, required for the native host without cannon"
+    )]
+    pub op_program_client: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -56,11 +195,47 @@ pub struct ProgramStats {
     pub num_preimage_requests: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub total_preimage_size: Option<u64>,
+    /// The fault proof status reported by the program.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<FaultProofStatus>,
+    /// The hash of cannon's final state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_hash: Option<B256>,
+    /// The hash of cannon's initial state, the absolute prestate of the dispute game.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prestate_hash: Option<B256>,
+    /// The progress cannon logged during the run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub progress: Vec<ProgressSample>,
+    /// The triage report of a run that panicked or never finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triage: Option<TriageReport>,
+}
+
+impl ProgramStats {
+    /// Adds the stats of another run to these stats.
+    ///
+    /// The status and state hashes are left untouched, as they have no meaningful aggregate.
+    pub fn accumulate(&mut self, other: &ProgramStats) {
+        fn add(total: &mut Option<u64>, value: Option<u64>) {
+            if let Some(value) = value {
+                *total = Some(total.unwrap_or_default() + value);
+            }
+        }
+
+        self.runtime += other.runtime;
+        add(&mut self.instructions, other.instructions);
+        add(&mut self.pages, other.pages);
+        add(&mut self.memory_used, other.memory_used);
+        add(&mut self.num_preimage_requests, other.num_preimage_requests);
+        add(&mut self.total_preimage_size, other.total_preimage_size);
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct CannonOutput {
     pub step: u64,
+    pub status: FaultProofStatus,
 }
 
 #[derive(Debug, Deserialize)]
@@ -74,63 +249,343 @@ struct CannonDebug {
 impl RunOpProgram {
     /// Runs the `run-op-program` subcommand.
     pub async fn run(&self) -> Result<()> {
-        let fixture = std::fs::read_to_string(&self.fixture)
-            .map_err(|e| eyre!("Failed to read fixture file: {}", e))?;
-        let fixture: FaultProofFixture = serde_json::from_str(&fixture)
-            .map_err(|e| eyre!("Failed to parse fixture file: {}", e))?;
-
-        let dirname = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_millis()
-            .to_string();
-        let data_dir = env::temp_dir().join("run-op-program").join(dirname);
-        if data_dir.exists() {
-            std::fs::remove_dir_all(&data_dir)?;
-        }
-        std::fs::create_dir_all(&data_dir)?;
-
-        let op_program_command =
-            OpProgramCommand::new(self.op_program.clone(), fixture, data_dir.clone());
+        let mut runner = FixtureRunner::new(
+            self.op_program.clone(),
+            self.cannon.clone(),
+            self.host.clone(),
+        )?;
+        let mut repeat = self.repeat;
+        if self.check_determinism {
+            if self.cannon.cannon.is_none() {
+                return Err(eyre!("Checking determinism requires cannon"));
+            }
+            runner.check_determinism = true;
+            repeat = repeat.max(2);
+        }
+        let outcome = runner.run_repeated(&self.fixture, repeat).await;
+        if let Some(benchmark) = &outcome.benchmark {
+            benchmark.print();
+        }
 
-        match self.cannon.as_ref() {
-            Some(cannon) => {
-                let cannon_command = CannonCommand::new(
-                    cannon.clone(),
-                    self.cannon_state
-                        .clone()
-                        .ok_or(eyre!("Missing cannon state"))?,
-                    self.cannon_meta
-                        .clone()
-                        .ok_or(eyre!("Missing cannon meta"))?,
-                    op_program_command,
-                );
-                cannon_command.prepare().await?;
-                let stats = cannon_command.run().await?;
-                info!(target: TARGET, "Cannon stats: {:?}", stats);
+        if let (Some(output), Some(stats)) = (&self.output, &outcome.stats) {
+            let file = std::fs::File::create(output)?;
+            serde_json::to_writer_pretty(file, stats)?;
+        }
+        self.reports.write(TARGET, std::slice::from_ref(&outcome))?;
+
+        if let Some(failure) = outcome.failure() {
+            error!(target: TARGET, "Fixture {:?} failed: {}", self.fixture, failure);
+            return Err(eyre!("Fixture {:?} failed: {}", self.fixture, failure));
+        }
+        info!(
+            target: TARGET,
+            "Fixture {:?} passed with status {:?}", self.fixture, outcome.expected_status
+        );
 
-                if let Some(output) = &self.output {
-                    let file = std::fs::File::create(output)?;
-                    serde_json::to_writer_pretty(file, &stats)?;
+        if let Some(stats) = &outcome.stats {
+            self.baseline.check(stats)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The outcome of running a single fixture.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureOutcome {
+    /// The path to the fixture file.
+    pub fixture: PathBuf,
+    /// The status the fixture expects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_status: Option<FaultProofStatus>,
+    /// The stats of the run, if the program could be executed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ProgramStats>,
+    /// The error that prevented the run from completing, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The resource budgets the fixture declares.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budgets: Option<ResourceBudgets>,
+    /// The runtime statistics, if the fixture was run repeatedly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub benchmark: Option<Benchmark>,
+}
+
+impl FixtureOutcome {
+    /// Creates the outcome of a fixture that hasn't been run yet.
+    pub fn new(fixture: PathBuf) -> Self {
+        Self {
+            fixture,
+            expected_status: None,
+            stats: None,
+            error: None,
+            budgets: None,
+            benchmark: None,
+        }
+    }
+
+    /// Returns the reason the fixture failed, or `None` if it passed.
+    pub fn failure(&self) -> Option<String> {
+        if let Some(error) = &self.error {
+            return Some(error.clone());
+        }
+        let status = self
+            .stats
+            .as_ref()
+            .and_then(|stats| stats.status)
+            .unwrap_or(FaultProofStatus::Unknown);
+        let mut failures = match self.expected_status {
+            Some(expected) if expected == status => vec![],
+            expected => vec![format!(
+                "expected status {:?}, got {:?}",
+                expected.unwrap_or(FaultProofStatus::Unknown),
+                status
+            )],
+        };
+        if let (Some(budgets), Some(stats)) = (&self.budgets, &self.stats) {
+            failures.extend(budgets.exceeded(
+                stats.instructions,
+                stats.memory_used,
+                stats.total_preimage_size,
+                u64::try_from(stats.runtime).ok(),
+            ));
+        }
+
+        if failures.is_empty() {
+            None
+        } else {
+            Some(failures.join("; "))
+        }
+    }
+
+    /// Returns true if the fixture passed.
+    pub fn passed(&self) -> bool {
+        self.failure().is_none()
+    }
+}
+
+/// Runs fixture files through op-program, or through cannon if it is configured.
+#[derive(Debug, Clone)]
+pub struct FixtureRunner {
+    /// The path to the op-program binary.
+    pub op_program: PathBuf,
+    /// The cannon configuration.
+    pub cannon: CannonArgs,
+    /// The native preimage host configuration.
+    pub host: HostArgs,
+    /// Whether to discard the output of the executed programs.
+    pub quiet: bool,
+    /// Whether to compare the final cannon states of repeated runs.
+    pub check_determinism: bool,
+}
+
+impl FixtureRunner {
+    /// Creates a new [FixtureRunner], checking that the cannon and host configurations are
+    /// complete.
+    pub fn new(op_program: PathBuf, cannon: CannonArgs, host: HostArgs) -> Result<Self> {
+        if cannon.cannon.is_some() {
+            cannon
+                .cannon_state
+                .as_ref()
+                .ok_or(eyre!("Missing cannon state"))?;
+            cannon
+                .cannon_meta
+                .as_ref()
+                .ok_or(eyre!("Missing cannon meta"))?;
+        } else if host.native_host && host.op_program_client.is_none() {
+            return Err(eyre!(
+                "The native host requires the op-program client binary when running without cannon"
+            ));
+        }
+
+        Ok(Self {
+            op_program,
+            cannon,
+            host,
+            quiet: false,
+            check_determinism: false,
+        })
+    }
+
+    /// Runs the fixture at the given path in its own data directory.
+    pub async fn run(&self, path: &Path) -> FixtureOutcome {
+        self.run_repeated(path, 1).await
+    }
+
+    /// Runs the fixture at the given path `repeat` times in a single data directory.
+    ///
+    /// With more than one run the outcome includes a [Benchmark] of the runs, and its stats
+    /// report the median runtime. Runs whose deterministic metrics differ fail the fixture.
+    pub async fn run_repeated(&self, path: &Path, repeat: usize) -> FixtureOutcome {
+        let mut outcome = FixtureOutcome::new(path.to_path_buf());
+
+        // Decoding and hashing the witness data is CPU bound, so it stays off the async workers.
+        let loaded = {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || load_fixture(&path)).await
+        };
+        let fixture = match loaded.map_err(|e| eyre!("Failed to load fixture: {}", e)) {
+            Ok(Ok(fixture)) => fixture,
+            Ok(Err(e)) | Err(e) => {
+                outcome.error = Some(e.to_string());
+                return outcome;
+            }
+        };
+        outcome.expected_status = Some(fixture.expected_status);
+        outcome.budgets = fixture.budgets.clone();
+
+        let runs = match self
+            .execute(fixture, Some(path), false, repeat.max(1))
+            .await
+        {
+            Ok((runs, _)) => runs,
+            Err(e) => {
+                outcome.error = Some(e.to_string());
+                return outcome;
+            }
+        };
+        let mut stats = runs[0].clone();
+        if runs.len() > 1 {
+            match Benchmark::new(&runs) {
+                Ok(benchmark) => {
+                    stats.runtime = benchmark.runtime.median.round() as u128;
+                    outcome.benchmark = Some(benchmark);
                 }
+                Err(e) => outcome.error = Some(e.to_string()),
             }
-            None => {
-                op_program_command.prepare().await?;
-                let stats = op_program_command.run().await?;
-                info!(target: TARGET, "op-program stats: {:?}", stats);
-
-                if let Some(output) = &self.output {
-                    let file = std::fs::File::create(output)?;
-                    serde_json::to_writer_pretty(file, &stats)?;
+        }
+        outcome.stats = Some(stats);
+        outcome
+    }
+
+    /// Runs the given fixture in a fresh data directory and returns its stats.
+    pub async fn run_fixture(&self, fixture: FaultProofFixture) -> Result<ProgramStats> {
+        let (mut runs, _) = self.execute(fixture, None, false, 1).await?;
+        Ok(runs.remove(0))
+    }
+
+    /// Runs the given fixture with the native host, and returns its stats along with the
+    /// preimage keys the client requested.
+    pub async fn run_fixture_tracked(
+        &self,
+        fixture: FaultProofFixture,
+    ) -> Result<(ProgramStats, BTreeSet<B256>)> {
+        if !self.host.native_host {
+            return Err(eyre!("Tracking preimage requests requires the native host"));
+        }
+        let (mut runs, accessed_keys) = self.execute(fixture, None, true, 1).await?;
+        Ok((runs.remove(0), accessed_keys))
+    }
+
+    /// Runs the given fixture `repeat` times in a fresh data directory, optionally tracking the
+    /// preimage keys requested from the native host.
+    ///
+    /// The data directory is only prepared once, so its setup isn't part of the measured runs.
+    async fn execute(
+        &self,
+        fixture: FaultProofFixture,
+        path: Option<&Path>,
+        track: bool,
+        repeat: usize,
+    ) -> Result<(Vec<ProgramStats>, BTreeSet<B256>)> {
+        let data_dir = unique_data_dir("run-op-program")?;
+        trace!(target: TARGET, "Using data directory: {:?}", data_dir);
+
+        let mut op_program_command =
+            OpProgramCommand::new(self.op_program.clone(), fixture, data_dir.clone());
+        op_program_command.quiet = self.quiet;
+        if self.host.native_host {
+            op_program_command.client = self.host.op_program_client.clone();
+        }
+        if track {
+            op_program_command.accessed_keys = Some(data_dir.join("accessed-keys.txt"));
+        }
+
+        let result = async {
+            let mut runs = Vec::with_capacity(repeat);
+            match self.cannon.cannon.as_ref() {
+                Some(cannon) => {
+                    let mut cannon_command = CannonCommand::new(
+                        cannon.clone(),
+                        self.cannon
+                            .cannon_state
+                            .clone()
+                            .ok_or(eyre!("Missing cannon state"))?,
+                        self.cannon
+                            .cannon_meta
+                            .clone()
+                            .ok_or(eyre!("Missing cannon meta"))?,
+                        op_program_command,
+                    );
+                    cannon_command.native_host = self.host.native_host;
+                    if let Some(info_at) = &self.cannon.cannon_info_at {
+                        cannon_command.info_at = info_at.clone();
+                    }
+                    // A client that loops forever would otherwise hang the run, without a
+                    // triage report.
+                    cannon_command.stop_at = self.cannon.cannon_stop_at.clone().or_else(|| {
+                        budget_stop_at(cannon_command.op_program.fixture.budgets.as_ref())
+                    });
+                    cannon_command.proof_at = self.cannon.cannon_proof_at.clone();
+                    cannon_command.snapshot_at = self.cannon.cannon_snapshot_at.clone();
+                    cannon_command.stop_at_preimage = self.cannon.cannon_stop_at_preimage.clone();
+                    cannon_command.artifacts_dir = self.cannon.artifacts_dir(path);
+                    cannon_command.prepare().await?;
+                    if self.check_determinism {
+                        runs = cannon_command.run_deterministic(repeat).await?;
+                    } else {
+                        for run in 1..=repeat {
+                            debug!(target: TARGET, "Starting run {}/{}", run, repeat);
+                            cannon_command.run = (repeat > 1).then_some(run);
+                            runs.push(cannon_command.run().await?);
+                        }
+                    }
+                }
+                None => {
+                    op_program_command.prepare().await?;
+                    for run in 1..=repeat {
+                        debug!(target: TARGET, "Starting run {}/{}", run, repeat);
+                        runs.push(op_program_command.run().await?);
+                    }
                 }
             }
+            Ok::<_, color_eyre::Report>(runs)
         }
+        .await;
+        let accessed_keys = match (&result, track) {
+            (Ok(_), true) => read_accessed_keys(&data_dir.join("accessed-keys.txt")),
+            _ => Ok(BTreeSet::new()),
+        };
 
         std::fs::remove_dir_all(&data_dir)?;
 
-        Ok(())
+        let runs = result?;
+        for stats in &runs {
+            info!(target: TARGET, "Program stats: {:?}", stats);
+        }
+        Ok((runs, accessed_keys?))
     }
 }
 
+/// Returns the step pattern that stops cannon one step past the fixture's step budget, if it
+/// declares one.
+fn budget_stop_at(budgets: Option<&ResourceBudgets>) -> Option<String> {
+    let max_steps = budgets?.max_steps?;
+    Some(format!("={}", max_steps.saturating_add(1)))
+}
+
+/// Reads and validates the fixture file at the given path.
+pub fn load_fixture(path: &Path) -> Result<FaultProofFixture> {
+    let fixture =
+        FaultProofFixture::load(path).map_err(|e| eyre!("Failed to read fixture file: {}", e))?;
+    fixture
+        .validate()
+        .map_err(|e| eyre!("Invalid fixture file: {}", e))?;
+    Ok(fixture)
+}
+
 /// The command to run the op-program within cannon.
 #[derive(Debug)]
 pub struct CannonCommand {
@@ -146,6 +601,25 @@ pub struct CannonCommand {
     pub debug: PathBuf,
     /// The op-program command to run within cannon.
     pub op_program: OpProgramCommand,
+    /// Whether cannon should run the native preimage host instead of op-program as its host.
+    pub native_host: bool,
+    /// The hash of the cannon state, once loaded by [CannonCommand::prepare].
+    pub prestate_hash: Option<B256>,
+    /// The step pattern at which cannon logs its progress.
+    pub info_at: String,
+    /// The step pattern at which cannon stops, if any.
+    pub stop_at: Option<String>,
+    /// The step pattern at which cannon writes proofs, if any.
+    pub proof_at: Option<String>,
+    /// The step pattern at which cannon writes state snapshots, if any.
+    pub snapshot_at: Option<String>,
+    /// The preimage key at which cannon stops, if any.
+    pub stop_at_preimage: Option<String>,
+    /// The directory proofs and snapshots are written to. It outlives the data directory.
+    pub artifacts_dir: Option<PathBuf>,
+    /// The number of the current run when cannon runs repeatedly, whose proofs and snapshots
+    /// are written to its own `run-<n>` subdirectory.
+    pub run: Option<usize>,
 }
 
 impl CannonCommand {
@@ -165,34 +639,135 @@ impl CannonCommand {
             output,
             debug,
             op_program,
+            native_host: false,
+            prestate_hash: None,
+            info_at: "%10000000".to_string(),
+            stop_at: None,
+            proof_at: None,
+            snapshot_at: None,
+            stop_at_preimage: None,
+            artifacts_dir: None,
+            run: None,
         }
     }
 
-    pub async fn prepare(&self) -> Result<()> {
-        self.op_program.prepare().await?;
+    /// Returns the path the fixture is written to for the native preimage host.
+    fn fixture_path(&self) -> PathBuf {
+        // The binary encoding is the fastest to write and read back.
+        self.op_program.data_dir.join("fixture.cbor")
+    }
+
+    pub async fn prepare(&mut self) -> Result<()> {
+        // Decompressing the prestate and computing its merkle root is CPU bound, so it stays off
+        // the async workers.
+        let loaded = {
+            let state = self.state.clone();
+            tokio::task::spawn_blocking(move || {
+                VersionedState::load(&state)
+                    .map(|prestate| (prestate.step(), prestate.state_hash()))
+            })
+            .await?
+        };
+        match loaded {
+            Ok((step, hash)) => {
+                info!(
+                    target: TARGET,
+                    "Prestate at step {} with hash {}",
+                    step,
+                    hash
+                );
+                self.prestate_hash = Some(hash);
+            }
+            Err(e) => warn!(target: TARGET, "Failed to load the prestate: {}", e),
+        }
+
+        if let Some(artifacts_dir) = &self.artifacts_dir {
+            std::fs::create_dir_all(artifacts_dir)?;
+            info!(
+                target: TARGET,
+                "Writing cannon proofs and snapshots to {:?}", artifacts_dir
+            );
+        }
+
+        if self.native_host {
+            self.op_program.fixture.save(&self.fixture_path())?;
+        } else {
+            self.op_program.prepare().await?;
+        }
 
         Ok(())
     }
 
     pub async fn run(&self) -> Result<ProgramStats> {
+        if let Some(artifacts_dir) = self.run_artifacts_dir() {
+            std::fs::create_dir_all(artifacts_dir)?;
+        }
+        // The output of a previous run must not be triaged as this run's.
+        if self.output.exists() {
+            std::fs::remove_file(&self.output)?;
+        }
+
         let start = std::time::Instant::now();
 
-        let result = Command::new(&self.cannon).args(self.args()).status();
+        let mut child = Command::new(&self.cannon)
+            .args(self.args())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| eyre!("Failed to execute cannon binary: {}", e))?;
 
-        if result.is_err() {
-            return Err(eyre!("Failed to execute cannon binary"));
+        // Cannon's output is forwarded line by line, picking out its progress on the way.
+        let quiet = self.op_program.quiet;
+        let stdout = child.stdout.take().ok_or(eyre!("Missing cannon stdout"))?;
+        let stderr = child.stderr.take().ok_or(eyre!("Missing cannon stderr"))?;
+        let stdout = tokio::spawn(progress::capture(stdout, start, quiet, false));
+        let stderr = tokio::spawn(progress::capture(stderr, start, quiet, true));
+
+        let result = child
+            .wait()
+            .await
+            .map_err(|e| eyre!("Failed to execute cannon binary: {}", e))?;
+        let mut progress = stdout.await??;
+        progress.extend(stderr.await??);
+        progress.sort_by_key(|sample| sample.step);
+        if let Some((from, to)) = progress::slowest_interval(&progress) {
+            debug!(
+                target: TARGET,
+                "Slowest progress between steps {} and {}: {} ms",
+                from.step,
+                to.step,
+                to.elapsed - from.elapsed
+            );
         }
 
-        let runtime = start.elapsed().as_millis();
+        if !result.success() {
+            let report = match self.final_state() {
+                Ok(state) => self.triage(&state),
+                Err(_) => None,
+            };
+            return Err(match report {
+                Some(report) => eyre!("Cannon exited with {}, {}", result, report),
+                None => eyre!("Cannon exited with {}", result),
+            });
+        }
 
-        let data =
-            std::fs::read(&self.output).map_err(|e| eyre!("Failed to read output file: {}", e))?;
+        let runtime = start.elapsed().as_millis();
 
-        let versioned_state = VersionedState::try_from(data)
-            .map_err(|e| eyre!("Failed to decode versioned state: {}", e))?;
+        let versioned_state = self.final_state()?;
         let output: CannonOutput = CannonOutput {
-            step: versioned_state.single_threaded_fpvmstate.step,
+            step: versioned_state.step(),
+            status: FaultProofStatus::from_vm(
+                versioned_state.exited(),
+                versioned_state.exit_code(),
+            ),
         };
+        debug!(
+            target: TARGET,
+            "Cannon exited: {}, exit code: {}",
+            versioned_state.exited(),
+            versioned_state.exit_code()
+        );
+        let triage = self.triage(&versioned_state);
 
         let debug_output = std::fs::read_to_string(&self.debug)
             .map_err(|e| eyre!("Failed to read debug output file: {}", e))?;
@@ -205,16 +780,78 @@ impl CannonCommand {
             memory_used: Some(debug_output.memory_used.to()),
             num_preimage_requests: Some(debug_output.num_preimage_requests),
             total_preimage_size: Some(debug_output.total_preimage_size),
+            status: Some(output.status),
+            state_hash: Some(versioned_state.state_hash()),
+            prestate_hash: self.prestate_hash,
+            progress,
+            triage,
         };
 
         Ok(stats)
     }
 
+    /// Builds a triage report if the final state panicked or never finished, symbolizing it with
+    /// the cannon metadata when it can be loaded.
+    fn triage(&self, state: &VersionedState) -> Option<TriageReport> {
+        let metadata = CannonMetadata::load(&self.meta)
+            .inspect_err(|e| warn!(target: TARGET, "Triage without symbols: {}", e))
+            .ok();
+        let report = TriageReport::new(state, metadata.as_ref())?;
+        warn!(target: TARGET, "Cannon {}", report);
+        Some(report)
+    }
+
+    /// Runs cannon `runs` times, checking that every run ends in the same final state.
+    ///
+    /// Returns the stats of each run, or an error naming the differing fields and memory pages
+    /// of the first run that diverged.
+    pub async fn run_deterministic(&mut self, runs: usize) -> Result<Vec<ProgramStats>> {
+        let mut stats = Vec::with_capacity(runs);
+        let mut first_state = None;
+        for run in 1..=runs {
+            debug!(target: TARGET, "Starting run {}/{}", run, runs);
+            self.run = (runs > 1).then_some(run);
+            stats.push(self.run().await?);
+
+            let state = self.final_state()?;
+            match &first_state {
+                None => first_state = Some(state),
+                Some(first_state) => {
+                    let diff = first_state.diff(&state);
+                    if !diff.is_empty() {
+                        return Err(eyre!(
+                            "Nondeterministic final state: run {} diverged from run 1 in {}",
+                            run,
+                            diff
+                        ));
+                    }
+                }
+            }
+        }
+
+        info!(target: TARGET, "Final state is identical across {} runs", runs);
+        Ok(stats)
+    }
+
+    /// Returns the directory the proofs and snapshots of the current run are written to.
+    pub fn run_artifacts_dir(&self) -> Option<PathBuf> {
+        let artifacts_dir = self.artifacts_dir.as_ref()?;
+        Some(match self.run {
+            Some(run) => artifacts_dir.join(format!("run-{}", run)),
+            None => artifacts_dir.clone(),
+        })
+    }
+
+    /// Reads and decodes the final state written by the last run.
+    pub fn final_state(&self) -> Result<VersionedState> {
+        VersionedState::load(&self.output)
+    }
+
     pub fn args(&self) -> Vec<String> {
         let mut args = vec![
             "run".to_string(),
             "--info-at".to_string(),
-            "%10000000".to_string(),
+            self.info_at.clone(),
             "--input".to_string(),
             self.state.to_str().unwrap().to_string(),
             "--meta".to_string(),
@@ -223,11 +860,61 @@ impl CannonCommand {
             self.output.to_str().unwrap().to_string(),
             "--debug-info".to_string(),
             self.debug.to_str().unwrap().to_string(),
-            "--".to_string(),
-            self.op_program.op_program.to_str().unwrap().to_string(),
         ];
-        args.extend(self.op_program.args());
-        args.push("--server".to_string());
+        if let Some(stop_at) = &self.stop_at {
+            args.push("--stop-at".to_string());
+            args.push(stop_at.clone());
+        }
+        if let Some(key) = &self.stop_at_preimage {
+            args.push("--stop-at-preimage".to_string());
+            args.push(key.clone());
+        }
+        // Without an artifacts directory, proofs and snapshots would be lost with the data
+        // directory, so they aren't requested.
+        if let Some(artifacts_dir) = self.run_artifacts_dir() {
+            if let Some(proof_at) = &self.proof_at {
+                args.push("--proof-at".to_string());
+                args.push(proof_at.clone());
+                args.push("--proof-fmt".to_string());
+                args.push(
+                    artifacts_dir
+                        .join("proof-%d.json")
+                        .to_str()
+                        .unwrap()
+                        .to_string(),
+                );
+            }
+            if let Some(snapshot_at) = &self.snapshot_at {
+                args.push("--snapshot-at".to_string());
+                args.push(snapshot_at.clone());
+                args.push("--snapshot-fmt".to_string());
+                args.push(
+                    artifacts_dir
+                        .join("state-%d.bin.gz")
+                        .to_str()
+                        .unwrap()
+                        .to_string(),
+                );
+            }
+        }
+        args.push("--".to_string());
+        if self.native_host {
+            let opfp = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("opfp"));
+            args.extend([
+                opfp.to_str().unwrap().to_string(),
+                "serve-preimages".to_string(),
+                "--fixture".to_string(),
+                self.fixture_path().to_str().unwrap().to_string(),
+            ]);
+            if let Some(path) = &self.op_program.accessed_keys {
+                args.push("--accessed-keys".to_string());
+                args.push(path.to_str().unwrap().to_string());
+            }
+        } else {
+            args.push(self.op_program.op_program.to_str().unwrap().to_string());
+            args.extend(self.op_program.args());
+            args.push("--server".to_string());
+        }
         args
     }
 }
@@ -241,6 +928,12 @@ pub struct OpProgramCommand {
     pub fixture: FaultProofFixture,
     /// The directory to store the input data for the op-program.
     pub data_dir: PathBuf,
+    /// Whether to discard the output of the op-program.
+    pub quiet: bool,
+    /// The op-program client binary to serve with the native preimage host, if any.
+    pub client: Option<PathBuf>,
+    /// The file the native host records the requested preimage keys to, if any.
+    pub accessed_keys: Option<PathBuf>,
 }
 
 impl OpProgramCommand {
@@ -249,10 +942,18 @@ impl OpProgramCommand {
             op_program,
             fixture,
             data_dir,
+            quiet: false,
+            client: None,
+            accessed_keys: None,
         }
     }
 
     pub async fn prepare(&self) -> Result<()> {
+        // The native host serves everything from memory.
+        if self.client.is_some() {
+            return Ok(());
+        }
+
         if let ChainDefinition::Unnamed(rollup_config, genesis) =
             &self.fixture.inputs.chain_definition
         {
@@ -283,18 +984,54 @@ impl OpProgramCommand {
     }
 
     pub async fn run(&self) -> Result<ProgramStats> {
+        if let Some(client) = &self.client {
+            return self.run_native(client).await;
+        }
+
         let start = std::time::Instant::now();
 
-        let result = Command::new(&self.op_program).args(self.args()).status();
+        let result = Command::new(&self.op_program)
+            .args(self.args())
+            .stdout(output_stdio(self.quiet))
+            .stderr(output_stdio(self.quiet))
+            .status()
+            .await
+            .map_err(|e| eyre!("Failed to execute op-program binary: {}", e))?;
 
-        if result.is_err() {
-            return Err(eyre!("Failed to execute op-program binary"));
+        let runtime = start.elapsed().as_millis();
+        debug!(target: TARGET, "op-program exited with {}", result);
+
+        Ok(ProgramStats {
+            runtime,
+            status: Some(exit_status(result)),
+            ..ProgramStats::default()
+        })
+    }
+
+    /// Runs the op-program client against the native preimage host.
+    async fn run_native(&self, client: &Path) -> Result<ProgramStats> {
+        let mut server = PreimageServer::new(&self.fixture)?;
+        if let Some(path) = &self.accessed_keys {
+            server = server.with_log(path)?;
         }
+        let server = Arc::new(server);
+
+        let start = std::time::Instant::now();
+
+        let mut command = Command::new(client);
+        command
+            .stdout(output_stdio(self.quiet))
+            .stderr(output_stdio(self.quiet));
+        let result = server.run_client(command).await?;
 
         let runtime = start.elapsed().as_millis();
+        debug!(target: TARGET, "op-program client exited with {}", result);
 
         Ok(ProgramStats {
             runtime,
+            num_preimage_requests: Some(server.num_requests()),
+            total_preimage_size: Some(server.total_size()),
+            status: Some(exit_status(result)),
             ..ProgramStats::default()
         })
     }
@@ -341,3 +1078,136 @@ impl OpProgramCommand {
         args
     }
 }
+
+/// Returns the fault proof status of an op-program exit status.
+fn exit_status(status: ExitStatus) -> FaultProofStatus {
+    // A missing exit code means op-program was terminated by a signal.
+    match status.code() {
+        Some(code) => u8::try_from(code)
+            .map(FaultProofStatus::from_exit_code)
+            .unwrap_or(FaultProofStatus::Unknown),
+        None => FaultProofStatus::Unknown,
+    }
+}
+
+/// Returns the stdio configuration for a program's output.
+fn output_stdio(quiet: bool) -> Stdio {
+    if quiet {
+        Stdio::null()
+    } else {
+        Stdio::inherit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_step_pattern() {
+        for pattern in ["never", "always", "=0", "=1000000", "%10000000"] {
+            assert_eq!(parse_step_pattern(pattern).unwrap(), pattern);
+        }
+        for pattern in ["", "sometimes", "%0", "=abc", "1000", "%-1"] {
+            assert!(parse_step_pattern(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    /// Returns the value following a flag in the arguments.
+    fn value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+        let i = args.iter().position(|arg| arg == flag)?;
+        Some(args[i + 1].as_str())
+    }
+
+    #[test]
+    fn test_cannon_args() {
+        let data_dir = PathBuf::from("/tmp/data");
+        let op_program = OpProgramCommand::new(
+            PathBuf::from("op-program"),
+            FaultProofFixture::default(),
+            data_dir,
+        );
+        let mut command = CannonCommand::new(
+            PathBuf::from("cannon"),
+            PathBuf::from("state.bin.gz"),
+            PathBuf::from("meta.json"),
+            op_program,
+        );
+        command.info_at = "%1000".to_string();
+        command.stop_at = Some("=5000".to_string());
+        command.proof_at = Some("=4999".to_string());
+        command.snapshot_at = Some("%1000".to_string());
+
+        let args = command.args();
+        assert_eq!(value(&args, "--info-at"), Some("%1000"));
+        assert_eq!(value(&args, "--stop-at"), Some("=5000"));
+        // Proofs and snapshots are only requested with a directory to keep them in.
+        assert_eq!(value(&args, "--proof-at"), None);
+        assert_eq!(value(&args, "--snapshot-at"), None);
+
+        command.artifacts_dir = Some(PathBuf::from("artifacts/Reader"));
+        let args = command.args();
+        assert_eq!(value(&args, "--proof-at"), Some("=4999"));
+        assert_eq!(
+            value(&args, "--proof-fmt"),
+            Some("artifacts/Reader/proof-%d.json")
+        );
+        assert_eq!(
+            value(&args, "--snapshot-fmt"),
+            Some("artifacts/Reader/state-%d.bin.gz")
+        );
+        assert_eq!(
+            args[args.iter().position(|arg| arg == "--").unwrap() + 1],
+            "op-program"
+        );
+
+        // Repeated runs keep their proofs and snapshots apart.
+        command.run = Some(2);
+        let args = command.args();
+        assert_eq!(
+            value(&args, "--proof-fmt"),
+            Some("artifacts/Reader/run-2/proof-%d.json")
+        );
+        assert_eq!(
+            value(&args, "--snapshot-fmt"),
+            Some("artifacts/Reader/run-2/state-%d.bin.gz")
+        );
+    }
+
+    #[test]
+    fn test_budget_stop_at() {
+        let budgets = |max_steps| ResourceBudgets {
+            max_steps,
+            ..Default::default()
+        };
+        assert_eq!(budget_stop_at(None), None);
+        assert_eq!(budget_stop_at(Some(&budgets(None))), None);
+        assert_eq!(
+            budget_stop_at(Some(&budgets(Some(1_000_000)))).as_deref(),
+            Some("=1000001")
+        );
+        assert_eq!(
+            budget_stop_at(Some(&budgets(Some(u64::MAX)))),
+            Some(format!("={}", u64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_artifacts_dir() {
+        let mut args = CannonArgs::default();
+        let fixture = Path::new("fixtures/Reader-2000000.json");
+        assert_eq!(args.artifacts_dir(Some(fixture)), None);
+
+        args.cannon_snapshot_at = Some("%1000".to_string());
+        assert_eq!(
+            args.artifacts_dir(Some(fixture)),
+            Some(PathBuf::from("cannon-artifacts/Reader-2000000"))
+        );
+        assert_eq!(
+            args.artifacts_dir(Some(Path::new("fixtures/Reader-2000000.json.gz"))),
+            Some(PathBuf::from("cannon-artifacts/Reader-2000000"))
+        );
+        args.cannon_artifacts = Some(PathBuf::from("out"));
+        assert_eq!(args.artifacts_dir(None), Some(PathBuf::from("out")));
+    }
+}
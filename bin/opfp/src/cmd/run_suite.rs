@@ -0,0 +1,277 @@
+//! Run Suite Subcommand
+
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use fp_test_fixtures::encoding::FixtureFormat;
+use futures::StreamExt;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::available_parallelism;
+use tracing::{info, warn};
+
+use super::report::ReportArgs;
+use super::run_op_program::{CannonArgs, FixtureOutcome, FixtureRunner, HostArgs, ProgramStats};
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "run-suite";
+
+/// CLI arguments for the `run-suite` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct RunSuite {
+    /// Path to the op-program binary
+    #[clap(short, long, help = "Path to the op-program binary")]
+    pub op_program: PathBuf,
+    /// Path to the directory containing the fixture files
+    #[clap(
+        short,
+        long,
+        help = "Path to the directory containing the fixture files"
+    )]
+    pub fixtures: PathBuf,
+    /// Optional cannon configuration
+    #[command(flatten)]
+    pub cannon: CannonArgs,
+    /// Native preimage host configuration
+    #[command(flatten)]
+    pub host: HostArgs,
+    /// Number of fixtures to run concurrently
+    #[clap(
+        short,
+        long,
+        help = "Number of fixtures to run concurrently (defaults to the number of CPUs)"
+    )]
+    pub jobs: Option<usize>,
+    /// Optional output file path for the suite summary
+    #[clap(long, help = "Path to the output file for the suite summary")]
+    pub output: Option<PathBuf>,
+    /// Optional test reports
+    #[command(flatten)]
+    pub reports: ReportArgs,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+/// The summary of a suite run.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuiteSummary {
+    /// The number of fixtures that passed.
+    pub passed: usize,
+    /// The number of fixtures that failed.
+    pub failed: usize,
+    /// The stats of all runs added together.
+    pub total: ProgramStats,
+    /// The outcome of each fixture.
+    pub outcomes: Vec<FixtureOutcome>,
+}
+
+impl SuiteSummary {
+    /// Builds the summary for the given fixture outcomes.
+    pub fn new(outcomes: Vec<FixtureOutcome>) -> Self {
+        let mut total = ProgramStats::default();
+        for stats in outcomes.iter().filter_map(|outcome| outcome.stats.as_ref()) {
+            total.accumulate(stats);
+        }
+        let passed = outcomes.iter().filter(|outcome| outcome.passed()).count();
+
+        Self {
+            passed,
+            failed: outcomes.len() - passed,
+            total,
+            outcomes,
+        }
+    }
+}
+
+impl RunSuite {
+    /// Runs the `run-suite` subcommand.
+    pub async fn run(&self) -> Result<()> {
+        let fixtures = find_fixtures(&self.fixtures)?;
+        if fixtures.is_empty() {
+            return Err(eyre!("No fixtures found in {:?}", self.fixtures));
+        }
+
+        let jobs = match self.jobs {
+            Some(jobs) => jobs.max(1),
+            None => available_parallelism().map(|n| n.get()).unwrap_or(1),
+        };
+        info!(
+            target: TARGET,
+            "Running {} fixtures with {} jobs",
+            fixtures.len(),
+            jobs
+        );
+
+        let mut runner = FixtureRunner::new(
+            self.op_program.clone(),
+            self.cannon.clone(),
+            self.host.clone(),
+        )?;
+        // Interleaved output from concurrent programs is unreadable, so only the summary is shown.
+        runner.quiet = true;
+        let runner = Arc::new(runner);
+
+        let outcomes = futures::stream::iter(fixtures)
+            .map(|fixture| {
+                let runner = runner.clone();
+                async move {
+                    // Each job is its own task, so the synchronous parts of one fixture's setup
+                    // don't stall the others.
+                    let job = {
+                        let fixture = fixture.clone();
+                        tokio::spawn(async move { runner.run(&fixture).await })
+                    };
+                    let outcome = job.await.unwrap_or_else(|e| {
+                        let mut outcome = FixtureOutcome::new(fixture.clone());
+                        outcome.error = Some(format!("Fixture job failed: {}", e));
+                        outcome
+                    });
+                    match outcome.failure() {
+                        Some(failure) => warn!(target: TARGET, "FAIL {:?}: {}", fixture, failure),
+                        None => info!(target: TARGET, "PASS {:?}", fixture),
+                    }
+                    outcome
+                }
+            })
+            .buffer_unordered(jobs)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut summary = SuiteSummary::new(outcomes);
+        summary.outcomes.sort_by(|a, b| a.fixture.cmp(&b.fixture));
+        print_summary(&summary);
+
+        if let Some(output) = &self.output {
+            let file = std::fs::File::create(output)?;
+            serde_json::to_writer_pretty(file, &summary)?;
+        }
+        self.reports.write(TARGET, &summary.outcomes)?;
+
+        if summary.failed > 0 {
+            return Err(eyre!(
+                "{} of {} fixtures failed",
+                summary.failed,
+                summary.outcomes.len()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Recursively finds all fixture files in the given directory, sorted by path.
+pub fn find_fixtures(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut fixtures = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            fixtures.extend(find_fixtures(&path)?);
+        } else if FixtureFormat::from_path(&path).is_some() {
+            fixtures.push(path);
+        }
+    }
+    fixtures.sort();
+    Ok(fixtures)
+}
+
+/// Prints a pass/fail line per fixture followed by the totals.
+fn print_summary(summary: &SuiteSummary) {
+    for outcome in &summary.outcomes {
+        let runtime = outcome.stats.as_ref().map(|stats| stats.runtime);
+        match outcome.failure() {
+            None => println!(
+                "PASS {} ({} ms)",
+                outcome.fixture.display(),
+                runtime.unwrap_or_default()
+            ),
+            Some(failure) => println!("FAIL {}: {}", outcome.fixture.display(), failure),
+        }
+    }
+
+    println!();
+    println!(
+        "{} passed, {} failed, {} total",
+        summary.passed,
+        summary.failed,
+        summary.outcomes.len()
+    );
+    println!("Total runtime: {} ms", summary.total.runtime);
+    if let Some(instructions) = summary.total.instructions {
+        println!("Total instructions: {}", instructions);
+    }
+    if let Some(requests) = summary.total.num_preimage_requests {
+        println!("Total preimage requests: {}", requests);
+    }
+    if let Some(size) = summary.total.total_preimage_size {
+        println!("Total preimage size: {}", size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::util::unique_data_dir;
+    use fp_test_fixtures::FaultProofStatus;
+
+    #[test]
+    fn test_find_fixtures() {
+        let dir = unique_data_dir("run-suite-test").unwrap();
+        std::fs::create_dir_all(dir.join("regressions/nested")).unwrap();
+        for name in [
+            "b.json",
+            "a.cbor.zst",
+            "notes.txt",
+            "regressions/c.json.gz",
+            "regressions/nested/d.cbor",
+            "regressions/README.md",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let fixtures = find_fixtures(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<_> = fixtures
+            .iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "a.cbor.zst",
+                "b.json",
+                "regressions/c.json.gz",
+                "regressions/nested/d.cbor"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_suite_summary() {
+        let outcome = |name: &str, status, instructions| {
+            let mut outcome = FixtureOutcome::new(PathBuf::from(name));
+            outcome.expected_status = Some(FaultProofStatus::Valid);
+            outcome.stats = Some(ProgramStats {
+                runtime: 10,
+                instructions: Some(instructions),
+                status: Some(status),
+                ..Default::default()
+            });
+            outcome
+        };
+        let mut errored = FixtureOutcome::new(PathBuf::from("broken.json"));
+        errored.error = Some("Failed to read fixture file".to_string());
+
+        let summary = SuiteSummary::new(vec![
+            outcome("a.json", FaultProofStatus::Valid, 100),
+            outcome("b.json", FaultProofStatus::Invalid, 50),
+            errored,
+        ]);
+        assert_eq!((summary.passed, summary.failed), (1, 2));
+        assert_eq!(summary.total.runtime, 20);
+        assert_eq!(summary.total.instructions, Some(150));
+        assert_eq!(summary.outcomes.len(), 3);
+    }
+}
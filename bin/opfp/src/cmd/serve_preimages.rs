@@ -0,0 +1,52 @@
+//! Serve Preimages Subcommand
+
+use clap::{ArgAction, Parser};
+use color_eyre::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::info;
+
+use super::preimage::PreimageServer;
+use super::run_op_program::load_fixture;
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "serve-preimages";
+
+/// CLI arguments for the `serve-preimages` subcommand of `opfp`.
+///
+/// This is the host process cannon runs with `--native-host`, serving the client on fds 3 to 6.
+#[derive(Parser, Clone, Debug)]
+pub struct ServePreimages {
+    /// Path to the fixture file
+    #[clap(short, long, help = "Path to the fixture file")]
+    pub fixture: PathBuf,
+    /// Optional path to record the accessed preimage keys to
+    #[clap(long, help = "Path to record the accessed preimage keys to")]
+    pub accessed_keys: Option<PathBuf>,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+impl ServePreimages {
+    /// Runs the `serve-preimages` subcommand.
+    pub async fn run(&self) -> Result<()> {
+        let fixture = load_fixture(&self.fixture)?;
+        let mut server = PreimageServer::new(&fixture)?;
+        if let Some(path) = &self.accessed_keys {
+            server = server.with_log(path)?;
+        }
+        let server = Arc::new(server);
+
+        let serve = server.clone();
+        tokio::task::spawn_blocking(move || serve.serve_stdio_fds()).await??;
+
+        info!(
+            target: TARGET,
+            "Served {} preimage requests ({} bytes)",
+            server.num_requests(),
+            server.total_size()
+        );
+        Ok(())
+    }
+}
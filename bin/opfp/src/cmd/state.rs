@@ -0,0 +1,529 @@
+//! State Subcommand
+
+use alloy_primitives::{Bytes, B256};
+use clap::{ArgAction, Parser};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::{debug, info};
+
+use super::mips::Mips32;
+use super::preimage::PreimageServer;
+use super::run_op_program::load_fixture;
+use super::triage::CannonMetadata;
+use super::util::{
+    Arch, FPVMState, MultiThreadedFPVMState, SingleThreadedFPVMState, ThreadState, VersionedState,
+    PAGE_SIZE,
+};
+
+/// The logging target to use for [tracing].
+const TARGET: &str = "state";
+
+/// The conventional names of the MIPS general purpose registers.
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
+/// CLI arguments for the `state` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct State {
+    /// The state subcommand to run
+    #[command(subcommand)]
+    pub command: StateCommand,
+}
+
+/// Subcommands for inspecting cannon states.
+#[derive(Parser, Clone, Debug)]
+pub enum StateCommand {
+    /// Prints the registers, CPU scalars, exit status and memory layout of a cannon state.
+    Inspect(Inspect),
+    /// Compares two cannon states field by field and lists their differing memory.
+    Diff(Diff),
+    /// Runs a single-threaded cannon state in the native MIPS interpreter.
+    Run(Run),
+}
+
+impl State {
+    /// Returns the verbosity level of the state subcommand.
+    pub fn v(&self) -> u8 {
+        match &self.command {
+            StateCommand::Inspect(cmd) => cmd.v,
+            StateCommand::Diff(cmd) => cmd.v,
+            StateCommand::Run(cmd) => cmd.v,
+        }
+    }
+
+    /// Runs the `state` subcommand.
+    pub async fn run(&self) -> Result<()> {
+        match &self.command {
+            StateCommand::Inspect(cmd) => cmd.run(),
+            StateCommand::Diff(cmd) => cmd.run(),
+            StateCommand::Run(cmd) => cmd.run(),
+        }
+    }
+}
+
+/// CLI arguments for the `state inspect` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct Inspect {
+    /// Path to the cannon state
+    #[clap(help = "Path to a binary, gzipped binary or JSON cannon state")]
+    pub state: PathBuf,
+    /// Optional cannon metadata to symbolize the program counters with
+    #[clap(
+        long,
+        help = "Path to the cannon metadata, to name the functions of program counters"
+    )]
+    pub meta: Option<PathBuf>,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+impl Inspect {
+    /// Runs the `state inspect` subcommand.
+    pub fn run(&self) -> Result<()> {
+        let state = VersionedState::load(&self.state)?;
+        let metadata = self.meta.as_deref().map(CannonMetadata::load).transpose()?;
+        debug!(target: TARGET, "Loaded {:?} at step {}", self.state, state.step());
+
+        let printer = Printer {
+            arch: state.arch(),
+            metadata: metadata.as_ref(),
+        };
+        let arch = match state.arch() {
+            Arch::Mips32 => "32-bit",
+            Arch::Mips64 => "64-bit",
+        };
+        let layout = match state.state {
+            FPVMState::SingleThreaded(_) => "single-threaded",
+            FPVMState::MultiThreaded(_) => "multi-threaded",
+        };
+        printer.field(
+            "version",
+            format!("{} ({}, {})", state.version, layout, arch),
+        );
+        printer.field("state hash", state.state_hash());
+        match &state.state {
+            FPVMState::SingleThreaded(inner) => printer.single_threaded(inner),
+            FPVMState::MultiThreaded(inner) => printer.multi_threaded(inner),
+        }
+        printer.memory(&state);
+
+        Ok(())
+    }
+}
+
+/// CLI arguments for the `state diff` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct Diff {
+    /// Path to the first cannon state
+    #[clap(help = "Path to the first cannon state")]
+    pub before: PathBuf,
+    /// Path to the second cannon state
+    #[clap(help = "Path to the second cannon state")]
+    pub after: PathBuf,
+    /// The maximum number of differing byte ranges to list per page
+    #[clap(
+        long,
+        default_value_t = 8,
+        help = "Maximum number of differing byte ranges to list per page"
+    )]
+    pub max_ranges: usize,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+impl Diff {
+    /// Runs the `state diff` subcommand.
+    pub fn run(&self) -> Result<()> {
+        let before = VersionedState::load(&self.before)?;
+        let after = VersionedState::load(&self.after)?;
+        let diff = before.diff(&after);
+        if diff.is_empty() {
+            println!("States are identical");
+            return Ok(());
+        }
+
+        if !diff.fields.is_empty() {
+            println!("{} differing fields", diff.fields.len());
+            let width = diff
+                .fields
+                .iter()
+                .map(|field| field.name.len())
+                .max()
+                .unwrap_or_default();
+            for field in &diff.fields {
+                println!(
+                    "  {:<width$}  {} -> {}",
+                    field.name,
+                    field.before,
+                    field.after,
+                    width = width
+                );
+            }
+        }
+
+        if !diff.pages.is_empty() {
+            println!("{} differing memory pages", diff.pages.len());
+            let (before, after) = (before.memory(), after.memory());
+            for page in &diff.pages {
+                let presence = match (
+                    before.pages.contains_key(page),
+                    after.pages.contains_key(page),
+                ) {
+                    (true, false) => " (only in first state)",
+                    (false, true) => " (only in second state)",
+                    _ => "",
+                };
+                let ranges = before.diff_bytes(after, *page);
+                let bytes: u64 = ranges
+                    .iter()
+                    .map(|range| range.end() - range.start() + 1)
+                    .sum();
+                let address = page.wrapping_mul(PAGE_SIZE as u64);
+                if bytes == 0 {
+                    // A zeroed page that only one state allocated holds the same bytes.
+                    println!(
+                        "  page {:#x} at {:#x}: allocation only{}",
+                        page, address, presence
+                    );
+                    continue;
+                }
+                println!(
+                    "  page {:#x} at {:#x}: {} bytes differ{}",
+                    page, address, bytes, presence
+                );
+                for range in ranges.iter().take(self.max_ranges) {
+                    println!(
+                        "    {:#x}..={:#x} (offset {:#x}, {} bytes)",
+                        range.start(),
+                        range.end(),
+                        range.start() % PAGE_SIZE as u64,
+                        range.end() - range.start() + 1
+                    );
+                }
+                if ranges.len() > self.max_ranges {
+                    println!("    ... {} more ranges", ranges.len() - self.max_ranges);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// CLI arguments for the `state run` subcommand of `opfp`.
+#[derive(Parser, Clone, Debug)]
+pub struct Run {
+    /// Path to the cannon state to start from
+    #[clap(help = "Path to the single-threaded cannon state to start from")]
+    pub state: PathBuf,
+    /// Path to the fixture file
+    #[clap(short, long, help = "Path to the fixture file providing the preimages")]
+    pub fixture: PathBuf,
+    /// Optional step to stop at
+    #[clap(
+        long,
+        help = "Step to stop at, instead of the exit or the step of --expect or --proof"
+    )]
+    pub stop_at: Option<u64>,
+    /// Optional state the run must end in
+    #[clap(
+        long,
+        help = "Path to a cannon state the run must end in, e.g. a later cannon snapshot"
+    )]
+    pub expect: Option<PathBuf>,
+    /// Optional cannon proof of the step to run
+    #[clap(
+        long,
+        conflicts_with_all = ["stop_at", "expect"],
+        help = "Path to a cannon proof whose pre-state is the given state, to check its post-state"
+    )]
+    pub proof: Option<PathBuf>,
+    /// Optional path to write the final state to
+    #[clap(long, help = "Path to write the final state to")]
+    pub output: Option<PathBuf>,
+    /// Whether to discard the program's output
+    #[clap(long, help = "Discard the program's stdout and stderr")]
+    pub quiet: bool,
+    /// Verbosity level (0-4)
+    #[arg(long, short, help = "Verbosity level (0-4)", action = ArgAction::Count)]
+    pub v: u8,
+}
+
+/// The step proof cannon writes at its `--proof-at` steps.
+#[derive(Debug, Deserialize)]
+pub struct CannonProof {
+    /// The step the proof starts at.
+    pub step: u64,
+    /// The state hash before the step.
+    pub pre: B256,
+    /// The state hash after the step.
+    pub post: B256,
+}
+
+impl CannonProof {
+    /// Loads a proof written by cannon.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).map_err(|e| eyre!("Failed to read proof {:?}: {}", path, e))?;
+        serde_json::from_slice(&data).map_err(|e| eyre!("Failed to parse proof {:?}: {}", path, e))
+    }
+}
+
+impl Run {
+    /// Runs the `state run` subcommand.
+    pub fn run(&self) -> Result<()> {
+        let fixture = load_fixture(&self.fixture)?;
+        let preimages = PreimageServer::new(&fixture)?;
+        let state = VersionedState::load(&self.state)?;
+        let expected = self
+            .expect
+            .as_deref()
+            .map(VersionedState::load)
+            .transpose()?;
+        let proof = self.proof.as_deref().map(CannonProof::load).transpose()?;
+
+        let stop_at = match (&proof, &expected) {
+            (Some(proof), _) => {
+                if state.step() != proof.step || state.state_hash() != proof.pre {
+                    return Err(eyre!(
+                        "The state at step {} with hash {} isn't the pre-state of the proof of step {} with hash {}",
+                        state.step(),
+                        state.state_hash(),
+                        proof.step,
+                        proof.pre
+                    ));
+                }
+                Some(proof.step + 1)
+            }
+            (None, Some(expected)) => self.stop_at.or(Some(expected.step())),
+            (None, None) => self.stop_at,
+        };
+
+        let start = Instant::now();
+        let from = state.step();
+        let mut mips = Mips32::new(state, &preimages)?.quiet(self.quiet);
+        mips.run(stop_at)?;
+        let state = mips.into_state();
+        info!(
+            target: TARGET,
+            "Ran {} steps in {} ms with {} preimage requests",
+            state.step() - from,
+            start.elapsed().as_millis(),
+            preimages.num_requests()
+        );
+
+        let status = if state.exited() {
+            format!("exited with code {}", state.exit_code())
+        } else {
+            "running".to_string()
+        };
+        println!(
+            "Step {}: {}, state hash {}",
+            state.step(),
+            status,
+            state.state_hash()
+        );
+        if let Some(path) = &self.output {
+            state.save(path)?;
+        }
+
+        if let Some(proof) = proof {
+            if state.state_hash() != proof.post {
+                return Err(eyre!(
+                    "The post-state hash {} doesn't match the proof's {}",
+                    state.state_hash(),
+                    proof.post
+                ));
+            }
+            println!("Proof of step {} verified", proof.step);
+        }
+        if let Some(expected) = expected {
+            let diff = state.diff(&expected);
+            if !diff.is_empty() {
+                return Err(eyre!(
+                    "The state at step {} differs from {:?} in {}",
+                    state.step(),
+                    self.expect.as_ref().unwrap(),
+                    diff
+                ));
+            }
+            println!("Matches {:?}", self.expect.as_ref().unwrap());
+        }
+
+        Ok(())
+    }
+}
+
+/// Prints the fields of a state as aligned name-value lines.
+struct Printer<'a> {
+    arch: Arch,
+    metadata: Option<&'a CannonMetadata>,
+}
+
+impl Printer<'_> {
+    /// Prints a named value.
+    fn field(&self, name: &str, value: impl std::fmt::Display) {
+        println!("{:<16} {}", name, value);
+    }
+
+    /// Formats a word, padded to the word size of the state.
+    fn word(&self, value: u64) -> String {
+        match self.arch {
+            Arch::Mips32 => format!("{:#010x}", value),
+            Arch::Mips64 => format!("{:#018x}", value),
+        }
+    }
+
+    /// Formats a code address, with its symbol if the metadata names it.
+    fn address(&self, value: u64) -> String {
+        match self.metadata.and_then(|metadata| metadata.symbolize(value)) {
+            Some(symbol) => format!("{} ({})", self.word(value), symbol),
+            None => self.word(value),
+        }
+    }
+
+    fn status(&self, exited: bool, exit_code: u8) {
+        if exited {
+            self.field("status", format!("exited with code {}", exit_code));
+        } else {
+            self.field("status", "running");
+        }
+    }
+
+    fn preimage(&self, key: B256, offset: u64, last_hint: &[u8]) {
+        self.field("preimage key", key);
+        self.field("preimage offset", offset);
+        let hint = match std::str::from_utf8(last_hint) {
+            Ok(hint) => format!("{:?}", hint),
+            Err(_) => Bytes::copy_from_slice(last_hint).to_string(),
+        };
+        self.field("last hint", hint);
+    }
+
+    fn registers<T: Copy + Into<u64>>(&self, indent: &str, registers: &[T; 32]) {
+        for (row, registers) in registers.chunks(4).enumerate() {
+            let line: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| {
+                    let index = row * 4 + i;
+                    format!("{:>4}  {}", REGISTER_NAMES[index], self.word(value.into()))
+                })
+                .collect();
+            println!("{}{}", indent, line.join("  "));
+        }
+    }
+
+    fn single_threaded(&self, state: &SingleThreadedFPVMState) {
+        self.field("step", state.step);
+        self.status(state.exited, state.exit_code);
+        self.field("pc", self.address(state.cpu.pc.into()));
+        self.field("next pc", self.address(state.cpu.next_pc.into()));
+        self.field("lo", self.word(state.cpu.lo.into()));
+        self.field("hi", self.word(state.cpu.hi.into()));
+        self.field("heap", self.word(state.heap.into()));
+        self.preimage(
+            state.preimage_key,
+            state.perimage_offset.into(),
+            &state.last_hint,
+        );
+        println!("registers");
+        self.registers("  ", &state.registers);
+    }
+
+    fn multi_threaded(&self, state: &MultiThreadedFPVMState) {
+        self.field("step", state.step);
+        self.status(state.exited, state.exit_code);
+        self.field("heap", self.word(state.heap));
+        self.preimage(state.preimage_key, state.preimage_offset, &state.last_hint);
+        self.field(
+            "ll reservation",
+            format!(
+                "status {} at {} by thread {}",
+                state.ll_reservation_status,
+                self.word(state.ll_address),
+                state.ll_owner_thread
+            ),
+        );
+        self.field("steps in thread", state.steps_since_last_context_switch);
+        self.field("wakeup", self.word(state.wakeup));
+        self.field("traverse right", state.traverse_right);
+        self.field("next thread id", state.next_thread_id);
+
+        let current = state.current_thread().map(|thread| thread.thread_id);
+        for (name, stack) in [
+            ("left", &state.left_thread_stack),
+            ("right", &state.right_thread_stack),
+        ] {
+            println!("{} thread stack ({} threads)", name, stack.len());
+            for thread in stack.iter().rev() {
+                self.thread(thread, current == Some(thread.thread_id));
+            }
+        }
+    }
+
+    fn thread(&self, thread: &ThreadState, current: bool) {
+        let status = if thread.exited {
+            format!("exited with code {}", thread.exit_code)
+        } else {
+            "running".to_string()
+        };
+        println!(
+            "  thread {}{}: {}",
+            thread.thread_id,
+            if current { " (current)" } else { "" },
+            status
+        );
+        println!(
+            "    pc {}, next pc {}",
+            self.address(thread.pc),
+            self.address(thread.next_pc)
+        );
+        println!(
+            "    lo {}, hi {}, futex {} = {} until step {}",
+            self.word(thread.lo),
+            self.word(thread.hi),
+            self.word(thread.futex_addr),
+            self.word(thread.futex_val),
+            thread.futex_timeout_step
+        );
+        self.registers("    ", &thread.registers);
+    }
+
+    fn memory(&self, state: &VersionedState) {
+        let memory = state.memory();
+        self.field(
+            "memory",
+            format!(
+                "{} pages ({} KiB), merkle root {}",
+                memory.pages.len(),
+                memory.pages.len() * PAGE_SIZE / 1024,
+                memory.merkle_root(self.arch)
+            ),
+        );
+        for range in memory.page_ranges() {
+            let pages = range.end() - range.start() + 1;
+            // The end is inclusive, as the last page of a 64-bit address space ends at 2^64.
+            let start = range.start().wrapping_mul(PAGE_SIZE as u64);
+            let end = range
+                .end()
+                .wrapping_mul(PAGE_SIZE as u64)
+                .wrapping_add(PAGE_SIZE as u64 - 1);
+            println!(
+                "  {}..={}  {} {}",
+                self.word(start),
+                self.word(end),
+                pages,
+                if pages == 1 { "page" } else { "pages" }
+            );
+        }
+    }
+}
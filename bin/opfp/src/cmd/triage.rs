@@ -0,0 +1,256 @@
+//! Triage reports for cannon runs that panicked or never finished.
+
+use alloy_primitives::{Bytes, B256};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::util::{FPVMState, VersionedState};
+
+/// The index of the return address register.
+const RETURN_ADDRESS_REGISTER: usize = 31;
+
+/// A symbol of the program, as listed in cannon's metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+    /// The name of the symbol.
+    pub name: String,
+    /// The address of the symbol.
+    pub start: u64,
+    /// The size of the symbol in bytes.
+    pub size: u64,
+}
+
+/// Cannon's metadata about the program, written alongside its initial state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CannonMetadata {
+    /// The symbols of the program, sorted by address.
+    pub symbols: Vec<Symbol>,
+}
+
+impl CannonMetadata {
+    /// Loads the metadata from a `meta.json` file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| eyre!("Failed to open cannon metadata {:?}: {}", path, e))?;
+        let mut metadata: Self = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| eyre!("Failed to parse cannon metadata {:?}: {}", path, e))?;
+        metadata.symbols.sort_by_key(|symbol| symbol.start);
+        Ok(metadata)
+    }
+
+    /// Writes the metadata to a `meta.json` file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| eyre!("Failed to create cannon metadata {:?}: {}", path, e))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)
+            .map_err(|e| eyre!("Failed to write cannon metadata {:?}: {}", path, e))
+    }
+
+    /// Returns the symbol containing the address, formatted as `name+0x<offset>`, or `None` if
+    /// no symbol contains it.
+    pub fn symbolize(&self, address: u64) -> Option<String> {
+        let i = self
+            .symbols
+            .partition_point(|symbol| symbol.start <= address);
+        let symbol = &self.symbols[i.checked_sub(1)?];
+        if address - symbol.start >= symbol.size.max(1) {
+            return None;
+        }
+        Some(format!("{}+{:#x}", symbol.name, address - symbol.start))
+    }
+}
+
+/// The context of a cannon run that panicked or never finished.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TriageReport {
+    /// Whether the program exited.
+    pub exited: bool,
+    /// The exit code of the program.
+    pub exit_code: u8,
+    /// The number of executed instructions.
+    pub step: u64,
+    /// The thread that was running, for multi-threaded states.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<u64>,
+    /// The program counter.
+    pub pc: u64,
+    /// The symbol of the program counter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pc_symbol: Option<String>,
+    /// The return address register.
+    pub return_address: u64,
+    /// The symbol of the return address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_address_symbol: Option<String>,
+    /// The last hint the program sent.
+    pub last_hint: Bytes,
+    /// The key of the last preimage the program read.
+    pub preimage_key: B256,
+    /// The offset into the last preimage the program read.
+    pub preimage_offset: u64,
+    /// The heap pointer.
+    pub heap: u64,
+}
+
+impl TriageReport {
+    /// Returns a triage report if the state exited with a nonzero exit code or never exited.
+    pub fn new(state: &VersionedState, metadata: Option<&CannonMetadata>) -> Option<Self> {
+        if state.exited() && state.exit_code() == 0 {
+            return None;
+        }
+
+        let mut report = match &state.state {
+            FPVMState::SingleThreaded(inner) => Self {
+                exited: inner.exited,
+                exit_code: inner.exit_code,
+                step: inner.step,
+                thread_id: None,
+                pc: inner.cpu.pc.into(),
+                pc_symbol: None,
+                return_address: inner.registers[RETURN_ADDRESS_REGISTER].into(),
+                return_address_symbol: None,
+                last_hint: inner.last_hint.clone().into(),
+                preimage_key: inner.preimage_key,
+                preimage_offset: inner.perimage_offset.into(),
+                heap: inner.heap.into(),
+            },
+            FPVMState::MultiThreaded(inner) => {
+                let thread = inner.current_thread();
+                Self {
+                    exited: inner.exited,
+                    exit_code: inner.exit_code,
+                    step: inner.step,
+                    thread_id: thread.map(|thread| thread.thread_id),
+                    pc: thread.map(|thread| thread.pc).unwrap_or_default(),
+                    pc_symbol: None,
+                    return_address: thread
+                        .map(|thread| thread.registers[RETURN_ADDRESS_REGISTER])
+                        .unwrap_or_default(),
+                    return_address_symbol: None,
+                    last_hint: inner.last_hint.clone().into(),
+                    preimage_key: inner.preimage_key,
+                    preimage_offset: inner.preimage_offset,
+                    heap: inner.heap,
+                }
+            }
+        };
+        if let Some(metadata) = metadata {
+            report.pc_symbol = metadata.symbolize(report.pc);
+            report.return_address_symbol = metadata.symbolize(report.return_address);
+        }
+        Some(report)
+    }
+}
+
+impl std::fmt::Display for TriageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = |symbol: &Option<String>| symbol.as_deref().unwrap_or("?").to_string();
+
+        if self.exited {
+            write!(f, "exited with code {}", self.exit_code)?;
+        } else {
+            write!(f, "unfinished")?;
+        }
+        write!(f, " at step {}", self.step)?;
+        if let Some(thread_id) = self.thread_id {
+            write!(f, " in thread {}", thread_id)?;
+        }
+        write!(
+            f,
+            ", pc {:#x} ({}), ra {:#x} ({}), heap {:#x}, preimage {} at offset {}",
+            self.pc,
+            symbol(&self.pc_symbol),
+            self.return_address,
+            symbol(&self.return_address_symbol),
+            self.heap,
+            self.preimage_key,
+            self.preimage_offset
+        )?;
+        match std::str::from_utf8(&self.last_hint) {
+            Ok(hint) if !hint.is_empty() => write!(f, ", last hint {:?}", hint),
+            Ok(_) => Ok(()),
+            Err(_) => write!(f, ", last hint {}", self.last_hint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::util::SingleThreadedFPVMState;
+
+    fn metadata() -> CannonMetadata {
+        let symbol = |name: &str, start, size| Symbol {
+            name: name.to_string(),
+            start,
+            size,
+        };
+        CannonMetadata {
+            symbols: vec![
+                symbol("runtime.text", 0x1000, 0x100),
+                symbol("runtime.gopanic", 0x2000, 0x200),
+                symbol("main.main", 0x3000, 0x80),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_symbolize() {
+        let metadata = metadata();
+        assert_eq!(metadata.symbolize(0xfff), None);
+        assert_eq!(
+            metadata.symbolize(0x1000).as_deref(),
+            Some("runtime.text+0x0")
+        );
+        assert_eq!(
+            metadata.symbolize(0x2010).as_deref(),
+            Some("runtime.gopanic+0x10")
+        );
+        assert_eq!(metadata.symbolize(0x2200), None);
+        assert_eq!(
+            metadata.symbolize(0x307c).as_deref(),
+            Some("main.main+0x7c")
+        );
+    }
+
+    #[test]
+    fn test_triage_report() {
+        let mut inner = SingleThreadedFPVMState {
+            exited: true,
+            step: 1234,
+            heap: 0x4000_0000,
+            last_hint: b"l2-block-header 0x01".to_vec(),
+            ..Default::default()
+        };
+        inner.cpu.pc = 0x2010;
+        inner.registers[31] = 0x3008;
+        let mut state = VersionedState {
+            version: 0,
+            state: FPVMState::SingleThreaded(inner),
+        };
+        assert_eq!(TriageReport::new(&state, Some(&metadata())), None);
+
+        let FPVMState::SingleThreaded(inner) = &mut state.state else {
+            unreachable!()
+        };
+        inner.exit_code = 2;
+        let report = TriageReport::new(&state, Some(&metadata())).unwrap();
+        assert_eq!(report.pc_symbol.as_deref(), Some("runtime.gopanic+0x10"));
+        assert_eq!(
+            report.return_address_symbol.as_deref(),
+            Some("main.main+0x8")
+        );
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "exited with code 2 at step 1234, pc 0x2010 (runtime.gopanic+0x10), ra 0x3008 \
+                 (main.main+0x8), heap 0x40000000, preimage {} at offset 0, last hint \
+                 \"l2-block-header 0x01\"",
+                B256::ZERO
+            )
+        );
+    }
+}
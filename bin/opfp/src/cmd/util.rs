@@ -1,11 +1,43 @@
 use alloy_eips::eip1559::BaseFeeParams;
-use alloy_primitives::{Address, B256};
+use alloy_primitives::{keccak256, Address, Bytes, B256, U64};
 use alloy_provider::{Provider, ReqwestProvider};
-use byteorder::{BigEndian, ReadBytesExt};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::io::{Cursor, Read};
+use std::io::{Cursor, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+/// Creates a new, empty data directory under `<temp dir>/<name>`.
+///
+/// The directory name combines the current time, the process id and a per-process counter,
+/// so concurrent jobs and concurrent `opfp` processes never share a directory.
+pub fn unique_data_dir(name: &str) -> Result<PathBuf> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dirname = format!(
+        "{}-{}-{}",
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis(),
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let parent = env::temp_dir().join(name);
+    std::fs::create_dir_all(&parent)?;
+
+    // Fails if the directory already exists, rather than silently sharing it.
+    let data_dir = parent.join(dirname);
+    std::fs::create_dir(&data_dir)?;
+    Ok(data_dir)
+}
 
 /// Represents the response containing the l2 output.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -95,6 +127,43 @@ impl RollupProvider {
     }
 }
 
+/// The gas used and transactions of a block.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockSummary {
+    /// The gas used by the block.
+    pub gas_used: U64,
+    /// The hashes of the transactions in the block.
+    pub transactions: Vec<B256>,
+}
+
+/// A provider for the L2 execution client.
+#[derive(Debug)]
+pub struct L2BlockProvider {
+    /// The inner Ethereum JSON-RPC provider.
+    inner: ReqwestProvider,
+}
+
+impl L2BlockProvider {
+    /// Creates a new [L2BlockProvider] from the provided [reqwest::Url].
+    pub fn new_http(url: reqwest::Url) -> Self {
+        Self {
+            inner: ReqwestProvider::new_http(url),
+        }
+    }
+
+    /// Returns the gas used and transactions of the block with the given number.
+    pub async fn block_summary(&self, block_number: u64) -> Result<BlockSummary> {
+        let block_num_hex = format!("0x{:x}", block_number);
+        let raw_block = self
+            .inner
+            .raw_request("eth_getBlockByNumber".into(), (block_num_hex, false))
+            .await?;
+        let block: BlockSummary = serde_json::from_value(raw_block)?;
+        Ok(block)
+    }
+}
+
 /// RollupConfig type compatible with the Optimism rollup node.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RollupConfig {
@@ -281,10 +350,80 @@ pub struct SystemConfig {
     pub gas_limit: u64,
 }
 
+/// The state version of the original single-threaded 32-bit VM.
+pub const VERSION_SINGLE_THREADED: u8 = 0;
+/// The state version of the original multi-threaded 32-bit VM.
+pub const VERSION_MULTI_THREADED: u8 = 1;
+/// The state version of the single-threaded 32-bit VM with `fcntl(F_GETFD)` support.
+pub const VERSION_SINGLE_THREADED_2: u8 = 2;
+/// The state version of the original multi-threaded 64-bit VM.
+pub const VERSION_MULTI_THREADED_64: u8 = 3;
+/// The second state version of the multi-threaded 64-bit VM.
+pub const VERSION_MULTI_THREADED_64_V2: u8 = 4;
+/// The second state version of the multi-threaded 32-bit VM.
+pub const VERSION_MULTI_THREADED_V2: u8 = 5;
+/// The third state version of the multi-threaded 64-bit VM, which changed the futex handling.
+/// Its layout isn't verified against a cannon state yet, so it is unsupported.
+pub const VERSION_MULTI_THREADED_64_V3: u8 = 6;
+/// The fourth state version of the multi-threaded 64-bit VM, unsupported like the third.
+pub const VERSION_MULTI_THREADED_64_V4: u8 = 7;
+
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct VersionedState {
     pub version: u8,
-    pub single_threaded_fpvmstate: SingleThreadedFPVMState,
+    pub state: FPVMState,
+}
+
+/// The state of the VM, in the layout given by the state version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FPVMState {
+    /// The state of the single-threaded 32-bit VM.
+    SingleThreaded(SingleThreadedFPVMState),
+    /// The state of the multi-threaded 32-bit or 64-bit VM.
+    MultiThreaded(MultiThreadedFPVMState),
+}
+
+impl Default for FPVMState {
+    fn default() -> Self {
+        FPVMState::SingleThreaded(SingleThreadedFPVMState::default())
+    }
+}
+
+/// The word size of the VM.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Arch {
+    /// 32-bit words.
+    #[default]
+    Mips32,
+    /// 64-bit words.
+    Mips64,
+}
+
+impl Arch {
+    /// Returns the depth of the memory merkle tree, with 32-byte leaves.
+    fn memory_depth(self) -> usize {
+        match self {
+            Arch::Mips32 => 27,
+            Arch::Mips64 => 59,
+        }
+    }
+
+    fn read_word<T>(self, cursor: &mut Cursor<T>) -> Result<u64>
+    where
+        T: AsRef<[u8]>,
+    {
+        Ok(match self {
+            Arch::Mips32 => cursor.read_u32::<BigEndian>()?.into(),
+            Arch::Mips64 => cursor.read_u64::<BigEndian>()?,
+        })
+    }
+
+    fn write_word(self, buffer: &mut Vec<u8>, word: u64) {
+        match self {
+            Arch::Mips32 => buffer.extend_from_slice(&(word as u32).to_be_bytes()),
+            Arch::Mips64 => buffer.extend_from_slice(&word.to_be_bytes()),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -302,9 +441,75 @@ pub struct SingleThreadedFPVMState {
     pub last_hint: Vec<u8>,
 }
 
+/// The state of the multi-threaded VM, with words widened to 64 bits for the 32-bit VM.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MultiThreadedFPVMState {
+    /// The memory shared by all threads.
+    pub memory: Memory,
+    /// The key of the preimage being read.
+    pub preimage_key: B256,
+    /// The offset into the preimage being read.
+    pub preimage_offset: u64,
+    /// The address of the next heap allocation.
+    pub heap: u64,
+    /// Whether a load-linked reservation is active, and of which width.
+    pub ll_reservation_status: u8,
+    /// The address of the load-linked reservation.
+    pub ll_address: u64,
+    /// The thread holding the load-linked reservation.
+    pub ll_owner_thread: u64,
+    /// The exit code of the program.
+    pub exit_code: u8,
+    /// Whether the program exited.
+    pub exited: bool,
+    /// The number of executed instructions.
+    pub step: u64,
+    /// The number of instructions executed by the current thread since it was scheduled.
+    pub steps_since_last_context_switch: u64,
+    /// The futex address the scheduler is waking threads for, or all ones if none.
+    pub wakeup: u64,
+    /// Whether the scheduler is traversing the right thread stack.
+    pub traverse_right: bool,
+    /// The ID of the next thread to be created.
+    pub next_thread_id: u64,
+    /// The left thread stack, with its top at the end.
+    pub left_thread_stack: Vec<ThreadState>,
+    /// The right thread stack, with its top at the end.
+    pub right_thread_stack: Vec<ThreadState>,
+    /// The last hint written by the program.
+    pub last_hint: Vec<u8>,
+}
+
+/// The state of a thread of the multi-threaded VM.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ThreadState {
+    /// The ID of the thread.
+    pub thread_id: u64,
+    /// The exit code of the thread.
+    pub exit_code: u8,
+    /// Whether the thread exited.
+    pub exited: bool,
+    /// The futex address the thread is waiting on, or all ones if none.
+    pub futex_addr: u64,
+    /// The value the thread expects at the futex address.
+    pub futex_val: u64,
+    /// The step at which the futex wait times out.
+    pub futex_timeout_step: u64,
+    /// The program counter.
+    pub pc: u64,
+    /// The next program counter, which differs from `pc + 4` in branch delay slots.
+    pub next_pc: u64,
+    /// The LO register.
+    pub lo: u64,
+    /// The HI register.
+    pub hi: u64,
+    /// The general purpose registers.
+    pub registers: [u64; 32],
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Memory {
-    pub pages: HashMap<u32, [u8; 4096]>,
+    pub pages: HashMap<u64, [u8; 4096]>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -315,42 +520,868 @@ pub struct CpuScalars {
     pub hi: u32,
 }
 
+impl VersionedState {
+    /// Returns the word size of the state version, and whether it is multi-threaded, or `None`
+    /// if the version is unsupported.
+    pub fn layout(version: u8) -> Option<(Arch, bool)> {
+        match version {
+            VERSION_SINGLE_THREADED | VERSION_SINGLE_THREADED_2 => Some((Arch::Mips32, false)),
+            VERSION_MULTI_THREADED | VERSION_MULTI_THREADED_V2 => Some((Arch::Mips32, true)),
+            VERSION_MULTI_THREADED_64 | VERSION_MULTI_THREADED_64_V2 => Some((Arch::Mips64, true)),
+            // These versions dropped the futex fields of the threads and the state's wakeup
+            // word, and are rejected until their layout is checked against cannon's.
+            VERSION_MULTI_THREADED_64_V3 | VERSION_MULTI_THREADED_64_V4 => None,
+            _ => None,
+        }
+    }
+
+    /// Returns the word size of the state.
+    pub fn arch(&self) -> Arch {
+        Self::layout(self.version)
+            .map(|(arch, _)| arch)
+            .unwrap_or_default()
+    }
+
+    /// Returns the memory of the state.
+    pub fn memory(&self) -> &Memory {
+        match &self.state {
+            FPVMState::SingleThreaded(state) => &state.memory,
+            FPVMState::MultiThreaded(state) => &state.memory,
+        }
+    }
+
+    /// Returns the number of executed instructions.
+    pub fn step(&self) -> u64 {
+        match &self.state {
+            FPVMState::SingleThreaded(state) => state.step,
+            FPVMState::MultiThreaded(state) => state.step,
+        }
+    }
+
+    /// Returns whether the program exited.
+    pub fn exited(&self) -> bool {
+        match &self.state {
+            FPVMState::SingleThreaded(state) => state.exited,
+            FPVMState::MultiThreaded(state) => state.exited,
+        }
+    }
+
+    /// Returns the exit code of the program.
+    pub fn exit_code(&self) -> u8 {
+        match &self.state {
+            FPVMState::SingleThreaded(state) => state.exit_code,
+            FPVMState::MultiThreaded(state) => state.exit_code,
+        }
+    }
+}
+
+impl MultiThreadedFPVMState {
+    /// Returns the thread the VM is currently executing, at the top of the active stack.
+    pub fn current_thread(&self) -> Option<&ThreadState> {
+        if self.traverse_right {
+            self.right_thread_stack.last()
+        } else {
+            self.left_thread_stack.last()
+        }
+    }
+}
+
+/// The differences between two cannon states.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// The differing fields, in the order of the state layout.
+    pub fields: Vec<FieldChange>,
+    /// The indices of the memory pages that differ or only exist in one of the states.
+    pub pages: Vec<u64>,
+}
+
+impl StateDiff {
+    /// Returns true if the states are identical.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.pages.is_empty()
+    }
+}
+
+impl std::fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        /// The number of page indices to list before summarizing the rest.
+        const MAX_PAGES: usize = 16;
+
+        let fields: Vec<&str> = self
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect();
+        write!(f, "fields: [{}]", fields.join(", "))?;
+        let pages: Vec<String> = self
+            .pages
+            .iter()
+            .take(MAX_PAGES)
+            .map(|page| format!("{:#x}", page))
+            .collect();
+        write!(f, ", pages: [{}", pages.join(", "))?;
+        if self.pages.len() > MAX_PAGES {
+            write!(f, ", ... {} more", self.pages.len() - MAX_PAGES)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// A field that differs between two cannon states.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+    /// The name of the field, with registers named by their index.
+    pub name: String,
+    /// The value in the first state.
+    pub before: String,
+    /// The value in the second state.
+    pub after: String,
+}
+
+impl FieldChange {
+    fn new(name: &str, before: impl FieldValue, after: impl FieldValue) -> Self {
+        Self {
+            name: name.to_string(),
+            before: before.format(),
+            after: after.format(),
+        }
+    }
+}
+
+/// Formats a state field for a [FieldChange].
+trait FieldValue {
+    fn format(&self) -> String;
+}
+
+impl FieldValue for u8 {
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl FieldValue for u32 {
+    fn format(&self) -> String {
+        format!("{:#x}", self)
+    }
+}
+
+impl FieldValue for u64 {
+    fn format(&self) -> String {
+        format!("{:#x}", self)
+    }
+}
+
+impl FieldValue for bool {
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl FieldValue for B256 {
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl FieldValue for Vec<u8> {
+    fn format(&self) -> String {
+        Bytes::copy_from_slice(self).to_string()
+    }
+}
+
+impl FieldValue for &str {
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<T: FieldValue> FieldValue for &T {
+    fn format(&self) -> String {
+        (*self).format()
+    }
+}
+
+/// Collects the differing fields.
+#[derive(Default)]
+struct FieldDiff {
+    fields: Vec<FieldChange>,
+}
+
+impl FieldDiff {
+    fn check<V: PartialEq + FieldValue>(&mut self, name: &str, a: V, b: V) {
+        if a != b {
+            self.fields.push(FieldChange::new(name, a, b));
+        }
+    }
+
+    fn check_registers<V: PartialEq + FieldValue>(&mut self, prefix: &str, a: &[V], b: &[V]) {
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            self.check(&format!("{}registers[{}]", prefix, i), a, b);
+        }
+    }
+}
+
+impl VersionedState {
+    /// Returns the differences between this state and another.
+    pub fn diff(&self, other: &VersionedState) -> StateDiff {
+        let mut diff = match (&self.state, &other.state) {
+            (FPVMState::SingleThreaded(a), FPVMState::SingleThreaded(b)) => a.diff(b),
+            (FPVMState::MultiThreaded(a), FPVMState::MultiThreaded(b)) => a.diff(b),
+            _ => {
+                let layout = |state: &VersionedState| match state.state {
+                    FPVMState::SingleThreaded(_) => "single-threaded",
+                    FPVMState::MultiThreaded(_) => "multi-threaded",
+                };
+                StateDiff {
+                    fields: vec![FieldChange::new("layout", layout(self), layout(other))],
+                    pages: self.memory().diff_pages(other.memory()),
+                }
+            }
+        };
+        if self.version != other.version {
+            diff.fields
+                .insert(0, FieldChange::new("version", self.version, other.version));
+        }
+        diff
+    }
+}
+
+impl SingleThreadedFPVMState {
+    /// Returns the differences between this state and another.
+    pub fn diff(&self, other: &SingleThreadedFPVMState) -> StateDiff {
+        let mut diff = FieldDiff::default();
+        diff.check("preimage_key", self.preimage_key, other.preimage_key);
+        diff.check(
+            "preimage_offset",
+            self.perimage_offset,
+            other.perimage_offset,
+        );
+        diff.check("pc", self.cpu.pc, other.cpu.pc);
+        diff.check("next_pc", self.cpu.next_pc, other.cpu.next_pc);
+        diff.check("lo", self.cpu.lo, other.cpu.lo);
+        diff.check("hi", self.cpu.hi, other.cpu.hi);
+        diff.check("heap", self.heap, other.heap);
+        diff.check("exit_code", self.exit_code, other.exit_code);
+        diff.check("exited", self.exited, other.exited);
+        diff.check("step", self.step, other.step);
+        diff.check_registers("", &self.registers, &other.registers);
+        diff.check("last_hint", &self.last_hint, &other.last_hint);
+
+        StateDiff {
+            fields: diff.fields,
+            pages: self.memory.diff_pages(&other.memory),
+        }
+    }
+}
+
+impl MultiThreadedFPVMState {
+    /// Returns the differences between this state and another, with thread fields named by
+    /// their stack and position, e.g. `left_thread_stack[0].pc`.
+    pub fn diff(&self, other: &MultiThreadedFPVMState) -> StateDiff {
+        let mut diff = FieldDiff::default();
+        diff.check("preimage_key", self.preimage_key, other.preimage_key);
+        diff.check(
+            "preimage_offset",
+            self.preimage_offset,
+            other.preimage_offset,
+        );
+        diff.check("heap", self.heap, other.heap);
+        diff.check(
+            "ll_reservation_status",
+            self.ll_reservation_status,
+            other.ll_reservation_status,
+        );
+        diff.check("ll_address", self.ll_address, other.ll_address);
+        diff.check(
+            "ll_owner_thread",
+            self.ll_owner_thread,
+            other.ll_owner_thread,
+        );
+        diff.check("exit_code", self.exit_code, other.exit_code);
+        diff.check("exited", self.exited, other.exited);
+        diff.check("step", self.step, other.step);
+        diff.check(
+            "steps_since_last_context_switch",
+            self.steps_since_last_context_switch,
+            other.steps_since_last_context_switch,
+        );
+        diff.check("wakeup", self.wakeup, other.wakeup);
+        diff.check("traverse_right", self.traverse_right, other.traverse_right);
+        diff.check("next_thread_id", self.next_thread_id, other.next_thread_id);
+        for (name, a, b) in [
+            (
+                "left_thread_stack",
+                &self.left_thread_stack,
+                &other.left_thread_stack,
+            ),
+            (
+                "right_thread_stack",
+                &self.right_thread_stack,
+                &other.right_thread_stack,
+            ),
+        ] {
+            if a.len() != b.len() {
+                diff.check(&format!("{}.len", name), a.len() as u64, b.len() as u64);
+                continue;
+            }
+            for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                a.diff(&format!("{}[{}].", name, i), b, &mut diff);
+            }
+        }
+        diff.check("last_hint", &self.last_hint, &other.last_hint);
+
+        StateDiff {
+            fields: diff.fields,
+            pages: self.memory.diff_pages(&other.memory),
+        }
+    }
+}
+
+impl ThreadState {
+    fn diff(&self, prefix: &str, other: &ThreadState, diff: &mut FieldDiff) {
+        let field = |name: &str| format!("{}{}", prefix, name);
+        diff.check(&field("thread_id"), self.thread_id, other.thread_id);
+        diff.check(&field("exit_code"), self.exit_code, other.exit_code);
+        diff.check(&field("exited"), self.exited, other.exited);
+        diff.check(&field("futex_addr"), self.futex_addr, other.futex_addr);
+        diff.check(&field("futex_val"), self.futex_val, other.futex_val);
+        diff.check(
+            &field("futex_timeout_step"),
+            self.futex_timeout_step,
+            other.futex_timeout_step,
+        );
+        diff.check(&field("pc"), self.pc, other.pc);
+        diff.check(&field("next_pc"), self.next_pc, other.next_pc);
+        diff.check(&field("lo"), self.lo, other.lo);
+        diff.check(&field("hi"), self.hi, other.hi);
+        diff.check_registers(prefix, &self.registers, &other.registers);
+    }
+}
+
+impl Memory {
+    /// Returns the sorted indices of the pages that differ or only exist in one of the memories.
+    pub fn diff_pages(&self, other: &Memory) -> Vec<u64> {
+        let mut pages: Vec<u64> = self
+            .pages
+            .iter()
+            .filter(|(index, page)| other.pages.get(index) != Some(page))
+            .map(|(index, _)| *index)
+            .chain(
+                other
+                    .pages
+                    .keys()
+                    .filter(|index| !self.pages.contains_key(index))
+                    .copied(),
+            )
+            .collect();
+        pages.sort_unstable();
+        pages
+    }
+
+    /// Returns the inclusive address ranges of the bytes that differ in a page, where a page
+    /// missing from one of the memories is zeroed.
+    pub fn diff_bytes(&self, other: &Memory, page: u64) -> Vec<RangeInclusive<u64>> {
+        const ZERO_PAGE: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+
+        let a = self.pages.get(&page).unwrap_or(&ZERO_PAGE);
+        let b = other.pages.get(&page).unwrap_or(&ZERO_PAGE);
+        let base = page.wrapping_mul(PAGE_SIZE as u64);
+        let mut ranges: Vec<RangeInclusive<u64>> = Vec::new();
+        for (offset, _) in a.iter().zip(b).enumerate().filter(|(_, (a, b))| a != b) {
+            let address = base.wrapping_add(offset as u64);
+            match ranges.last_mut() {
+                Some(range) if range.end().wrapping_add(1) == address => {
+                    *range = *range.start()..=address
+                }
+                _ => ranges.push(address..=address),
+            }
+        }
+        ranges
+    }
+
+    /// Writes data starting at an address, allocating zeroed pages as needed.
+    pub fn set_range(&mut self, address: u64, data: &[u8]) {
+        let (mut address, mut data) = (address, data);
+        while !data.is_empty() {
+            let offset = (address % PAGE_SIZE as u64) as usize;
+            let len = data.len().min(PAGE_SIZE - offset);
+            let page = self
+                .pages
+                .entry(address / PAGE_SIZE as u64)
+                .or_insert([0; PAGE_SIZE]);
+            page[offset..offset + len].copy_from_slice(&data[..len]);
+            address = address.wrapping_add(len as u64);
+            data = &data[len..];
+        }
+    }
+
+    /// Writes a big-endian word of the given word size at an address.
+    pub fn set_word(&mut self, arch: Arch, address: u64, word: u64) {
+        let mut data = Vec::with_capacity(8);
+        arch.write_word(&mut data, word);
+        self.set_range(address, &data);
+    }
+
+    /// Returns the sorted ranges of consecutive allocated page indices.
+    pub fn page_ranges(&self) -> Vec<RangeInclusive<u64>> {
+        let mut pages: Vec<u64> = self.pages.keys().copied().collect();
+        pages.sort_unstable();
+        let mut ranges: Vec<RangeInclusive<u64>> = Vec::new();
+        for page in pages {
+            match ranges.last_mut() {
+                Some(range) if *range.end() + 1 == page => *range = *range.start()..=page,
+                _ => ranges.push(page..=page),
+            }
+        }
+        ranges
+    }
+}
+
+/// The size of a memory page in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// The size of the single-threaded state witness in bytes.
+pub const STATE_WITNESS_SIZE: usize = 226;
+
+/// The VM status of a state that exited with exit code 0.
+pub const VM_STATUS_VALID: u8 = 0;
+/// The VM status of a state that exited with exit code 1.
+pub const VM_STATUS_INVALID: u8 = 1;
+/// The VM status of a state that exited with any other exit code.
+pub const VM_STATUS_PANIC: u8 = 2;
+/// The VM status of a state that hasn't exited.
+pub const VM_STATUS_UNFINISHED: u8 = 3;
+
+/// Returns the VM status for the exit state of the program.
+pub fn vm_status(exited: bool, exit_code: u8) -> u8 {
+    match (exited, exit_code) {
+        (false, _) => VM_STATUS_UNFINISHED,
+        (true, 0) => VM_STATUS_VALID,
+        (true, 1) => VM_STATUS_INVALID,
+        (true, _) => VM_STATUS_PANIC,
+    }
+}
+
+/// Returns the state hash of a witness: its hash with the first byte replaced by the VM status.
+fn state_hash(witness: &[u8], exited: bool, exit_code: u8) -> B256 {
+    let mut hash = keccak256(witness);
+    hash[0] = vm_status(exited, exit_code);
+    hash
+}
+
+impl VersionedState {
+    /// Returns the state hash, as committed to by the dispute game.
+    pub fn state_hash(&self) -> B256 {
+        match &self.state {
+            FPVMState::SingleThreaded(state) => state.state_hash(),
+            FPVMState::MultiThreaded(state) => state.state_hash(self.arch()),
+        }
+    }
+}
+
+impl SingleThreadedFPVMState {
+    /// Returns the state witness: the memory root followed by the fixed-size state fields.
+    pub fn witness(&self) -> [u8; STATE_WITNESS_SIZE] {
+        let mut witness = Vec::with_capacity(STATE_WITNESS_SIZE);
+        witness.extend_from_slice(self.memory.merkle_root(Arch::Mips32).as_slice());
+        witness.extend_from_slice(self.preimage_key.as_slice());
+        for value in [
+            self.perimage_offset,
+            self.cpu.pc,
+            self.cpu.next_pc,
+            self.cpu.lo,
+            self.cpu.hi,
+            self.heap,
+        ] {
+            witness.extend_from_slice(&value.to_be_bytes());
+        }
+        witness.push(self.exit_code);
+        witness.push(self.exited as u8);
+        witness.extend_from_slice(&self.step.to_be_bytes());
+        for register in self.registers {
+            witness.extend_from_slice(&register.to_be_bytes());
+        }
+        witness
+            .try_into()
+            .expect("the witness fields add up to the witness size")
+    }
+
+    /// Returns the state hash: the hash of the witness with its first byte replaced by the VM
+    /// status.
+    pub fn state_hash(&self) -> B256 {
+        state_hash(&self.witness(), self.exited, self.exit_code)
+    }
+}
+
+impl MultiThreadedFPVMState {
+    /// Returns the state witness, which commits to the thread stacks by their roots.
+    pub fn witness(&self, arch: Arch) -> Vec<u8> {
+        let mut witness = Vec::new();
+        witness.extend_from_slice(self.memory.merkle_root(arch).as_slice());
+        witness.extend_from_slice(self.preimage_key.as_slice());
+        arch.write_word(&mut witness, self.preimage_offset);
+        arch.write_word(&mut witness, self.heap);
+        witness.push(self.ll_reservation_status);
+        arch.write_word(&mut witness, self.ll_address);
+        arch.write_word(&mut witness, self.ll_owner_thread);
+        witness.push(self.exit_code);
+        witness.push(self.exited as u8);
+        witness.extend_from_slice(&self.step.to_be_bytes());
+        witness.extend_from_slice(&self.steps_since_last_context_switch.to_be_bytes());
+        arch.write_word(&mut witness, self.wakeup);
+        witness.push(self.traverse_right as u8);
+        witness.extend_from_slice(thread_stack_root(&self.left_thread_stack, arch).as_slice());
+        witness.extend_from_slice(thread_stack_root(&self.right_thread_stack, arch).as_slice());
+        arch.write_word(&mut witness, self.next_thread_id);
+        witness
+    }
+
+    /// Returns the state hash: the hash of the witness with its first byte replaced by the VM
+    /// status.
+    pub fn state_hash(&self, arch: Arch) -> B256 {
+        state_hash(&self.witness(arch), self.exited, self.exit_code)
+    }
+}
+
+/// Returns the root of a thread stack, which hashes each thread onto the root of the threads
+/// below it, starting from the hash of two zero words.
+fn thread_stack_root(stack: &[ThreadState], arch: Arch) -> B256 {
+    stack
+        .iter()
+        .fold(hash_pair(&B256::ZERO, &B256::ZERO), |root, thread| {
+            let mut witness = Vec::new();
+            thread.encode(&mut witness, arch);
+            hash_pair(&root, &keccak256(witness))
+        })
+}
+
+impl Memory {
+    /// The depth of the merkle tree of a page, with 32-byte leaves.
+    const PAGE_DEPTH: usize = 7;
+
+    /// Returns the merkle root of the memory, where missing pages are zeroed.
+    pub fn merkle_root(&self, arch: Arch) -> B256 {
+        let zero_hashes = zero_hashes();
+
+        let mut nodes: HashMap<u64, B256> = self
+            .pages
+            .iter()
+            .map(|(index, page)| (*index, page_root(page)))
+            .collect();
+        for zero_hash in &zero_hashes[Self::PAGE_DEPTH..arch.memory_depth()] {
+            let mut parents = HashMap::with_capacity(nodes.len() / 2 + 1);
+            for index in nodes.keys() {
+                let parent = index >> 1;
+                if parents.contains_key(&parent) {
+                    continue;
+                }
+                let child = |index| nodes.get(&index).unwrap_or(zero_hash);
+                parents.insert(
+                    parent,
+                    hash_pair(child(parent << 1), child((parent << 1) | 1)),
+                );
+            }
+            nodes = parents;
+        }
+
+        nodes.remove(&0).unwrap_or(zero_hashes[arch.memory_depth()])
+    }
+}
+
+/// Returns the hashes of fully zeroed subtrees, indexed by their depth.
+fn zero_hashes() -> [B256; 60] {
+    let mut hashes = [B256::ZERO; 60];
+    let mut hash = B256::ZERO;
+    for zero_hash in hashes.iter_mut().skip(1) {
+        hash = hash_pair(&hash, &hash);
+        *zero_hash = hash;
+    }
+    hashes
+}
+
+/// Returns the merkle root of a page.
+fn page_root(page: &[u8; PAGE_SIZE]) -> B256 {
+    let mut nodes: Vec<B256> = page.chunks(32).map(B256::from_slice).collect();
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+    }
+    nodes[0]
+}
+
+/// Hashes two merkle nodes into their parent.
+fn hash_pair(left: &B256, right: &B256) -> B256 {
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(left.as_slice());
+    data[32..].copy_from_slice(right.as_slice());
+    keccak256(data)
+}
+
 trait Decodable {
-    fn decode<T>(&mut self, cursor: &mut Cursor<T>) -> Result<()>
+    fn decode<T>(&mut self, cursor: &mut Cursor<T>, arch: Arch) -> Result<()>
     where
         T: AsRef<[u8]>;
 }
 
+trait Encodable {
+    fn encode(&self, buffer: &mut Vec<u8>, arch: Arch);
+}
+
 impl TryFrom<Vec<u8>> for VersionedState {
     type Error = String;
 
     fn try_from(buffer: Vec<u8>) -> Result<Self, Self::Error> {
-        let mut v = VersionedState::default();
         let mut cursor = Cursor::new(buffer);
-        let result = v.decode(&mut cursor);
+        let result = VersionedState::decode(&mut cursor);
         return match result {
-            Ok(_) => Ok(v),
+            Ok(v) => Ok(v),
             Err(err) => Err(format!("invalid versioned state encoding: {err}").to_string()),
         };
     }
 }
 
-impl Decodable for VersionedState {
-    fn decode<T>(&mut self, cursor: &mut Cursor<T>) -> Result<()>
+impl VersionedState {
+    /// Loads a state file written by cannon, in the binary or legacy JSON format, either of
+    /// which may be gzip-compressed.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)
+            .map_err(|e| eyre!("Failed to read state file {:?}: {}", path, e))?;
+        Self::parse(data).map_err(|e| eyre!("Failed to decode state file {:?}: {}", path, e))
+    }
+
+    /// Decodes a state in the binary or legacy JSON format, decompressing it first if it is
+    /// gzip-compressed.
+    pub fn parse(data: Vec<u8>) -> Result<Self> {
+        /// The magic bytes at the start of a gzip stream.
+        const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+        if data.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+            return Self::parse(decompressed);
+        }
+        // Binary states start with their version, which is never a JSON object's opening brace.
+        if data.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+            let state: JsonState = serde_json::from_slice(&data)?;
+            return state.into_state();
+        }
+        Self::try_from(data).map_err(|e| eyre!(e))
+    }
+
+    /// Writes the state in the binary format, gzip-compressed if the path ends in `.gz`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut data = Vec::<u8>::from(self);
+        if path.extension().is_some_and(|extension| extension == "gz") {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&data)?;
+            data = encoder.finish()?;
+        }
+        std::fs::write(path, data)
+            .map_err(|e| eyre!("Failed to write state file {:?}: {}", path, e))
+    }
+}
+
+/// A single-threaded state in cannon's legacy JSON format.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonState {
+    memory: Vec<JsonPage>,
+    preimage_key: B256,
+    preimage_offset: u32,
+    pc: u32,
+    #[serde(rename = "nextPC")]
+    next_pc: u32,
+    lo: u32,
+    hi: u32,
+    heap: u32,
+    #[serde(rename = "exit")]
+    exit_code: u8,
+    exited: bool,
+    step: u64,
+    registers: [u32; 32],
+    #[serde(default)]
+    last_hint: Option<Bytes>,
+}
+
+/// A memory page in cannon's legacy JSON format, with its data zlib-compressed and base64-encoded.
+#[derive(Deserialize)]
+struct JsonPage {
+    index: u32,
+    data: String,
+}
+
+impl JsonState {
+    /// Converts the state into a single-threaded [VersionedState], decompressing its pages.
+    fn into_state(self) -> Result<VersionedState> {
+        let mut memory = Memory::default();
+        for page in self.memory {
+            let compressed = BASE64_STANDARD
+                .decode(&page.data)
+                .map_err(|e| eyre!("Invalid encoding of page {}: {}", page.index, e))?;
+            let mut data = Vec::with_capacity(PAGE_SIZE);
+            ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut data)?;
+            let data: [u8; PAGE_SIZE] = data.try_into().map_err(|data: Vec<u8>| {
+                eyre!(
+                    "Page {} has {} bytes, expected {}",
+                    page.index,
+                    data.len(),
+                    PAGE_SIZE
+                )
+            })?;
+            memory.pages.insert(page.index.into(), data);
+        }
+
+        Ok(VersionedState {
+            version: VERSION_SINGLE_THREADED,
+            state: FPVMState::SingleThreaded(SingleThreadedFPVMState {
+                memory,
+                preimage_key: self.preimage_key,
+                perimage_offset: self.preimage_offset,
+                cpu: CpuScalars {
+                    pc: self.pc,
+                    next_pc: self.next_pc,
+                    lo: self.lo,
+                    hi: self.hi,
+                },
+                heap: self.heap,
+                exit_code: self.exit_code,
+                exited: self.exited,
+                step: self.step,
+                registers: self.registers,
+                last_hint: self.last_hint.map(Vec::from).unwrap_or_default(),
+            }),
+        })
+    }
+}
+
+impl From<&VersionedState> for Vec<u8> {
+    fn from(state: &VersionedState) -> Self {
+        let mut buffer = vec![state.version];
+        match &state.state {
+            FPVMState::SingleThreaded(inner) => inner.encode(&mut buffer, state.arch()),
+            FPVMState::MultiThreaded(inner) => inner.encode(&mut buffer, state.arch()),
+        }
+        buffer
+    }
+}
+
+impl Encodable for SingleThreadedFPVMState {
+    fn encode(&self, buffer: &mut Vec<u8>, arch: Arch) {
+        // Writes to a Vec can't fail.
+        self.memory.encode(buffer, arch);
+        buffer.extend_from_slice(self.preimage_key.as_slice());
+        for value in [
+            self.perimage_offset,
+            self.cpu.pc,
+            self.cpu.next_pc,
+            self.cpu.lo,
+            self.cpu.hi,
+            self.heap,
+        ] {
+            buffer.write_u32::<BigEndian>(value).unwrap();
+        }
+        buffer.push(self.exit_code);
+        buffer.push(self.exited as u8);
+        buffer.write_u64::<BigEndian>(self.step).unwrap();
+        for register in self.registers {
+            buffer.write_u32::<BigEndian>(register).unwrap();
+        }
+        buffer
+            .write_u32::<BigEndian>(self.last_hint.len() as u32)
+            .unwrap();
+        buffer.extend_from_slice(&self.last_hint);
+    }
+}
+
+impl Encodable for MultiThreadedFPVMState {
+    fn encode(&self, buffer: &mut Vec<u8>, arch: Arch) {
+        self.memory.encode(buffer, arch);
+        buffer.extend_from_slice(self.preimage_key.as_slice());
+        arch.write_word(buffer, self.preimage_offset);
+        arch.write_word(buffer, self.heap);
+        buffer.push(self.ll_reservation_status);
+        arch.write_word(buffer, self.ll_address);
+        arch.write_word(buffer, self.ll_owner_thread);
+        buffer.push(self.exit_code);
+        buffer.push(self.exited as u8);
+        buffer.extend_from_slice(&self.step.to_be_bytes());
+        buffer.extend_from_slice(&self.steps_since_last_context_switch.to_be_bytes());
+        arch.write_word(buffer, self.wakeup);
+        buffer.push(self.traverse_right as u8);
+        arch.write_word(buffer, self.next_thread_id);
+        for stack in [&self.left_thread_stack, &self.right_thread_stack] {
+            arch.write_word(buffer, stack.len() as u64);
+            for thread in stack {
+                thread.encode(buffer, arch);
+            }
+        }
+        buffer.extend_from_slice(&(self.last_hint.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(&self.last_hint);
+    }
+}
+
+impl Encodable for ThreadState {
+    fn encode(&self, buffer: &mut Vec<u8>, arch: Arch) {
+        arch.write_word(buffer, self.thread_id);
+        buffer.push(self.exit_code);
+        buffer.push(self.exited as u8);
+        arch.write_word(buffer, self.futex_addr);
+        arch.write_word(buffer, self.futex_val);
+        buffer.extend_from_slice(&self.futex_timeout_step.to_be_bytes());
+        for word in [self.pc, self.next_pc, self.lo, self.hi] {
+            arch.write_word(buffer, word);
+        }
+        for register in self.registers {
+            arch.write_word(buffer, register);
+        }
+    }
+}
+
+impl Encodable for Memory {
+    fn encode(&self, buffer: &mut Vec<u8>, arch: Arch) {
+        // Pages are written in ascending order, like cannon does, so encodings are canonical.
+        let mut indices: Vec<&u64> = self.pages.keys().collect();
+        indices.sort_unstable();
+
+        buffer.write_u32::<BigEndian>(indices.len() as u32).unwrap();
+        for index in indices {
+            arch.write_word(buffer, *index);
+            buffer.extend_from_slice(&self.pages[index]);
+        }
+    }
+}
+
+impl VersionedState {
+    fn decode<T>(cursor: &mut Cursor<T>) -> Result<Self>
     where
         T: AsRef<[u8]>,
     {
-        self.version = cursor.read_u8()?;
-        self.single_threaded_fpvmstate.decode(cursor)
+        let version = cursor.read_u8()?;
+        let (arch, multi_threaded) =
+            Self::layout(version).ok_or_else(|| eyre!("unsupported state version {}", version))?;
+
+        let state = if multi_threaded {
+            let mut state = MultiThreadedFPVMState::default();
+            state.decode(cursor, arch)?;
+            FPVMState::MultiThreaded(state)
+        } else {
+            let mut state = SingleThreadedFPVMState::default();
+            state.decode(cursor, arch)?;
+            FPVMState::SingleThreaded(state)
+        };
+
+        Ok(Self { version, state })
     }
 }
 
 impl Decodable for SingleThreadedFPVMState {
-    fn decode<T>(&mut self, cursor: &mut Cursor<T>) -> Result<()>
+    fn decode<T>(&mut self, cursor: &mut Cursor<T>, arch: Arch) -> Result<()>
     where
         T: AsRef<[u8]>,
     {
-        self.memory.decode(cursor)?;
+        self.memory.decode(cursor, arch)?;
 
         let mut preimage_key_buffer: [u8; 32] = [0; 32];
         cursor.read_exact(&mut preimage_key_buffer)?;
@@ -372,12 +1403,72 @@ impl Decodable for SingleThreadedFPVMState {
             self.registers[i] = cursor.read_u32::<BigEndian>()?;
         }
 
-        let last_hint_len = cursor.read_u32::<BigEndian>()?;
-        if last_hint_len > 0 {
-            let mut slice = vec![0; last_hint_len.try_into().unwrap()];
-            cursor.read_exact(&mut slice)?;
+        self.last_hint = read_last_hint(cursor)?;
+
+        Ok(())
+    }
+}
+
+impl Decodable for MultiThreadedFPVMState {
+    fn decode<T>(&mut self, cursor: &mut Cursor<T>, arch: Arch) -> Result<()>
+    where
+        T: AsRef<[u8]>,
+    {
+        self.memory.decode(cursor, arch)?;
+
+        let mut preimage_key_buffer: [u8; 32] = [0; 32];
+        cursor.read_exact(&mut preimage_key_buffer)?;
+        self.preimage_key = B256::from(&preimage_key_buffer);
+        self.preimage_offset = arch.read_word(cursor)?;
+        self.heap = arch.read_word(cursor)?;
+
+        self.ll_reservation_status = cursor.read_u8()?;
+        self.ll_address = arch.read_word(cursor)?;
+        self.ll_owner_thread = arch.read_word(cursor)?;
+
+        self.exit_code = cursor.read_u8()?;
+        self.exited = cursor.read_u8()? != 0;
+        self.step = cursor.read_u64::<BigEndian>()?;
+        self.steps_since_last_context_switch = cursor.read_u64::<BigEndian>()?;
+        self.wakeup = arch.read_word(cursor)?;
+        self.traverse_right = cursor.read_u8()? != 0;
+        self.next_thread_id = arch.read_word(cursor)?;
+
+        for stack in [&mut self.left_thread_stack, &mut self.right_thread_stack] {
+            let len = arch.read_word(cursor)?;
+            stack.clear();
+            for _ in 0..len {
+                let mut thread = ThreadState::default();
+                thread.decode(cursor, arch)?;
+                stack.push(thread);
+            }
+        }
+
+        self.last_hint = read_last_hint(cursor)?;
+
+        Ok(())
+    }
+}
 
-            self.last_hint = slice;
+impl Decodable for ThreadState {
+    fn decode<T>(&mut self, cursor: &mut Cursor<T>, arch: Arch) -> Result<()>
+    where
+        T: AsRef<[u8]>,
+    {
+        self.thread_id = arch.read_word(cursor)?;
+        self.exit_code = cursor.read_u8()?;
+        self.exited = cursor.read_u8()? != 0;
+        self.futex_addr = arch.read_word(cursor)?;
+        self.futex_val = arch.read_word(cursor)?;
+        self.futex_timeout_step = cursor.read_u64::<BigEndian>()?;
+
+        self.pc = arch.read_word(cursor)?;
+        self.next_pc = arch.read_word(cursor)?;
+        self.lo = arch.read_word(cursor)?;
+        self.hi = arch.read_word(cursor)?;
+
+        for register in self.registers.iter_mut() {
+            *register = arch.read_word(cursor)?;
         }
 
         Ok(())
@@ -385,7 +1476,7 @@ impl Decodable for SingleThreadedFPVMState {
 }
 
 impl Decodable for Memory {
-    fn decode<T>(&mut self, cursor: &mut Cursor<T>) -> Result<()>
+    fn decode<T>(&mut self, cursor: &mut Cursor<T>, arch: Arch) -> Result<()>
     where
         T: AsRef<[u8]>,
     {
@@ -396,7 +1487,7 @@ impl Decodable for Memory {
         }
 
         for _i in 0..page_count {
-            let page_index = cursor.read_u32::<BigEndian>()?;
+            let page_index = arch.read_word(cursor)?;
             let mut data: [u8; 4096] = [0; 4096];
             cursor.read_exact(&mut data)?;
             self.pages.insert(page_index, data);
@@ -406,9 +1497,26 @@ impl Decodable for Memory {
     }
 }
 
+/// Reads the length-prefixed last hint of a state.
+fn read_last_hint<T>(cursor: &mut Cursor<T>) -> Result<Vec<u8>>
+where
+    T: AsRef<[u8]>,
+{
+    let last_hint_len = cursor.read_u32::<BigEndian>()?;
+    let mut slice = vec![0; last_hint_len.try_into().unwrap()];
+    cursor.read_exact(&mut slice)?;
+    Ok(slice)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::cmd::util::{CpuScalars, Memory, SingleThreadedFPVMState, VersionedState};
+    use crate::cmd::util::{
+        hash_pair, keccak256, thread_stack_root, vm_status, zero_hashes, Arch, CpuScalars,
+        Encodable, FPVMState, FieldChange, Memory, MultiThreadedFPVMState, SingleThreadedFPVMState,
+        StateDiff, ThreadState, VersionedState, STATE_WITNESS_SIZE, VERSION_MULTI_THREADED_64_V2,
+        VERSION_MULTI_THREADED_64_V3, VERSION_MULTI_THREADED_64_V4, VERSION_MULTI_THREADED_V2,
+        VM_STATUS_UNFINISHED, VM_STATUS_VALID,
+    };
     use alloy_primitives::{hex, Uint, B256};
     use std::collections::HashMap;
     use std::fs;
@@ -457,8 +1565,250 @@ mod tests {
 
         let test_data = hex!("020000000200000005000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000007b00000100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ff0000000000000000000000000000000000000000000000000000000000000000000005000000ff000001030000beef0000babe00c0ffee010100000000deadbeefdeadbeefdeadbeef00c0ffeebeefbabedeadc0de0badc0dedeaddead00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000050102030405");
         let test_data_vec: Vec<u8> = test_data.to_vec();
-        let v = VersionedState::try_from(test_data_vec).unwrap();
+        let v = VersionedState::try_from(test_data_vec.clone()).unwrap();
+
+        assert_eq!(v.state, FPVMState::SingleThreaded(correct_state));
+        assert_eq!(Vec::<u8>::from(&v), test_data_vec);
+    }
+
+    #[test]
+    fn test_state_diff() {
+        let mut a = SingleThreadedFPVMState::default();
+        a.memory.pages.insert(1, [0; 4096]);
+        a.memory.pages.insert(2, [0; 4096]);
+        let mut b = a.clone();
+        assert!(a.diff(&b).is_empty());
+
+        b.step = 1;
+        b.registers[29] = 0x7fff_0000;
+        b.memory.pages.get_mut(&2).unwrap()[100] = 1;
+        b.memory.pages.insert(7, [0; 4096]);
+        a.memory.pages.insert(0x7b, [0; 4096]);
+
+        let diff = a.diff(&b);
+        assert_eq!(
+            diff,
+            StateDiff {
+                fields: vec![
+                    FieldChange::new("step", 0u64, 1u64),
+                    FieldChange::new("registers[29]", 0u32, 0x7fff_0000u32),
+                ],
+                pages: vec![2, 7, 0x7b],
+            }
+        );
+        assert_eq!(diff.fields[1].after, "0x7fff0000");
+        assert_eq!(
+            diff.to_string(),
+            "fields: [step, registers[29]], pages: [0x2, 0x7, 0x7b]"
+        );
+        assert_eq!(a.memory.diff_bytes(&b.memory, 2), vec![0x2064..=0x2064]);
+        assert_eq!(a.memory.diff_bytes(&b.memory, 7), vec![]);
+        assert_eq!(a.memory.page_ranges(), vec![1..=2, 0x7b..=0x7b]);
 
-        assert_eq!(v.single_threaded_fpvmstate, correct_state);
+        // The last page of a 64-bit address space ends at the last address.
+        let top = u64::MAX / 4096;
+        b.memory.set_range(u64::MAX - 1, &[1, 2]);
+        assert_eq!(
+            a.memory.diff_bytes(&b.memory, top),
+            vec![u64::MAX - 1..=u64::MAX]
+        );
+    }
+
+    #[test]
+    fn test_memory_merkle_root() {
+        let zero_hashes = zero_hashes();
+        let mut memory = Memory::default();
+        assert_eq!(memory.merkle_root(Arch::Mips32), zero_hashes[27]);
+
+        // Zeroed pages hash like missing ones.
+        memory.pages.insert(3, [0; 4096]);
+        assert_eq!(memory.merkle_root(Arch::Mips32), zero_hashes[27]);
+
+        // The first leaf of the address space, with every sibling on its path zeroed.
+        memory.pages.insert(0, [0; 4096]);
+        memory.pages.get_mut(&0).unwrap()[31] = 1;
+        let mut expected = B256::with_last_byte(1);
+        for zero_hash in &zero_hashes[..27] {
+            expected = hash_pair(&expected, zero_hash);
+        }
+        assert_eq!(memory.merkle_root(Arch::Mips32), expected);
+
+        memory.pages.get_mut(&3).unwrap()[0] = 1;
+        assert_ne!(memory.merkle_root(Arch::Mips32), expected);
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let mut state = SingleThreadedFPVMState {
+            step: 0x0102030405060708,
+            ..Default::default()
+        };
+        state.registers[31] = 0xdeadbeef;
+
+        let witness = state.witness();
+        assert_eq!(witness.len(), STATE_WITNESS_SIZE);
+        assert_eq!(&witness[..32], zero_hashes()[27].as_slice());
+        assert_eq!(&witness[90..98], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&witness[222..], &[0xde, 0xad, 0xbe, 0xef]);
+
+        let hash = state.state_hash();
+        assert_eq!(hash[0], VM_STATUS_UNFINISHED);
+        assert_eq!(hash[1..], keccak256(witness)[1..]);
+
+        state.exited = true;
+        assert_eq!(state.state_hash()[0], VM_STATUS_VALID);
+        assert_eq!(vm_status(true, 1), 1);
+        assert_eq!(vm_status(true, 42), 2);
+    }
+
+    fn multi_threaded_state() -> MultiThreadedFPVMState {
+        let thread = |thread_id| ThreadState {
+            thread_id,
+            pc: 0x1000 + thread_id * 4,
+            next_pc: 0x1004 + thread_id * 4,
+            futex_addr: 0xffff_fffc,
+            registers: [thread_id; 32],
+            ..Default::default()
+        };
+        let mut state = MultiThreadedFPVMState {
+            preimage_key: B256::repeat_byte(0xab),
+            preimage_offset: 8,
+            heap: 0x2000_0000,
+            ll_reservation_status: 1,
+            ll_address: 0x3000,
+            ll_owner_thread: 1,
+            step: 1_000_000,
+            steps_since_last_context_switch: 42,
+            wakeup: u32::MAX.into(),
+            traverse_right: true,
+            next_thread_id: 3,
+            left_thread_stack: vec![thread(0)],
+            right_thread_stack: vec![thread(1), thread(2)],
+            last_hint: vec![1, 2, 3],
+            ..Default::default()
+        };
+        let mut page = [0; 4096];
+        page[7] = 0x42;
+        state.memory.pages.insert(0x7fff, page);
+        state
+    }
+
+    #[test]
+    fn test_multi_threaded_roundtrip() {
+        for (version, arch, state_size, thread_size) in [
+            (VERSION_MULTI_THREADED_V2, Arch::Mips32, 172, 166),
+            (VERSION_MULTI_THREADED_64_V2, Arch::Mips64, 196, 322),
+        ] {
+            let state = VersionedState {
+                version,
+                state: FPVMState::MultiThreaded(multi_threaded_state()),
+            };
+            let encoded = Vec::<u8>::from(&state);
+            let decoded = VersionedState::try_from(encoded).unwrap();
+            assert_eq!(decoded, state);
+            assert_eq!(decoded.arch(), arch);
+            assert_eq!(decoded.step(), 1_000_000);
+
+            let FPVMState::MultiThreaded(inner) = &decoded.state else {
+                panic!("expected a multi-threaded state");
+            };
+            assert_eq!(inner.current_thread().unwrap().thread_id, 2);
+            assert_eq!(inner.witness(arch).len(), state_size);
+            let mut thread = Vec::new();
+            inner.left_thread_stack[0].encode(&mut thread, arch);
+            assert_eq!(thread.len(), thread_size);
+            assert_eq!(decoded.state_hash()[0], VM_STATUS_UNFINISHED);
+        }
+
+        // The layouts of the later 64-bit versions aren't verified.
+        for version in [VERSION_MULTI_THREADED_64_V3, VERSION_MULTI_THREADED_64_V4] {
+            let state = VersionedState {
+                version: VERSION_MULTI_THREADED_64_V2,
+                state: FPVMState::MultiThreaded(multi_threaded_state()),
+            };
+            let mut encoded = Vec::<u8>::from(&state);
+            encoded[0] = version;
+            assert!(VersionedState::layout(version).is_none());
+            assert!(VersionedState::try_from(encoded).is_err());
+        }
+    }
+
+    #[test]
+    fn test_thread_stack_root() {
+        let empty = thread_stack_root(&[], Arch::Mips32);
+        assert_eq!(
+            empty,
+            B256::from(hex!(
+                "ad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5"
+            ))
+        );
+
+        let thread = ThreadState::default();
+        let mut witness = Vec::new();
+        thread.encode(&mut witness, Arch::Mips32);
+        assert_eq!(
+            thread_stack_root(&[thread], Arch::Mips32),
+            hash_pair(&empty, &keccak256(witness))
+        );
+    }
+
+    #[test]
+    fn test_decode_unsupported_version() {
+        let err = VersionedState::try_from(vec![0xff, 0, 0, 0, 0]).unwrap_err();
+        assert!(err.contains("unsupported state version 255"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_state_formats() {
+        use base64::prelude::{Engine, BASE64_STANDARD};
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut page = [0u8; 4096];
+        page[..4].copy_from_slice(&[1, 2, 3, 4]);
+        let mut state = SingleThreadedFPVMState {
+            heap: 0x2000_0000,
+            step: 42,
+            last_hint: vec![0xaa],
+            ..Default::default()
+        };
+        state.cpu.next_pc = 4;
+        state.registers[2] = 7;
+        state.memory.pages.insert(3, page);
+        let state = VersionedState {
+            version: 0,
+            state: FPVMState::SingleThreaded(state),
+        };
+
+        let binary = Vec::<u8>::from(&state);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&binary).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&page).unwrap();
+        let mut registers = [0u32; 32];
+        registers[2] = 7;
+        let json = serde_json::json!({
+            "memory": [{ "index": 3, "data": BASE64_STANDARD.encode(zlib.finish().unwrap()) }],
+            "preimageKey": B256::ZERO,
+            "preimageOffset": 0,
+            "pc": 0,
+            "nextPC": 4,
+            "lo": 0,
+            "hi": 0,
+            "heap": 0x2000_0000,
+            "exit": 0,
+            "exited": false,
+            "step": 42,
+            "registers": registers,
+            "lastHint": "0xaa"
+        });
+        let json = serde_json::to_vec(&json).unwrap();
+
+        for data in [binary, gzip, json] {
+            assert_eq!(VersionedState::parse(data).unwrap(), state);
+        }
     }
 }
@@ -0,0 +1,347 @@
+//! Encodings of fixture files, chosen by the file extension.
+//!
+//! Fixtures are either JSON or CBOR, optionally compressed with gzip or zstd, e.g.
+//! `fixture.json`, `fixture.json.gz` or `fixture.cbor.zst`. CBOR stores the witness data as raw
+//! bytes instead of hex strings, which halves the size of a fixture before compression.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use alloy_primitives::{Bytes, B256};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{migration, FaultProofFixture, FIXTURE_VERSION};
+
+/// The serialization format of a fixture file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureEncoding {
+    /// JSON, with the witness data hex-encoded.
+    Json,
+    /// CBOR, with the witness data stored as raw bytes.
+    Cbor,
+}
+
+/// The compression of a fixture file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureCompression {
+    /// Uncompressed.
+    None,
+    /// Compressed with gzip.
+    Gzip,
+    /// Compressed with zstd.
+    Zstd,
+}
+
+/// The format of a fixture file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixtureFormat {
+    /// The serialization format.
+    pub encoding: FixtureEncoding,
+    /// The compression.
+    pub compression: FixtureCompression,
+}
+
+impl FixtureFormat {
+    /// Returns the format of a fixture file from its extensions, or `None` if they aren't those
+    /// of a fixture file.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        let (name, compression) = if let Some(name) = name.strip_suffix(".gz") {
+            (name, FixtureCompression::Gzip)
+        } else if let Some(name) = name.strip_suffix(".zst") {
+            (name, FixtureCompression::Zstd)
+        } else {
+            (name.as_str(), FixtureCompression::None)
+        };
+        let encoding = if name.ends_with(".json") {
+            FixtureEncoding::Json
+        } else if name.ends_with(".cbor") {
+            FixtureEncoding::Cbor
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            encoding,
+            compression,
+        })
+    }
+}
+
+/// Returns the name of a fixture file without its encoding and compression extensions, e.g.
+/// `Reader` for `fixtures/Reader.json.gz`, or `None` if it isn't a fixture file.
+pub fn fixture_stem(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy().into_owned();
+    if FixtureFormat::from_path(path)?.compression == FixtureCompression::None {
+        return Some(stem);
+    }
+    // The compression extension is gone, which leaves the encoding extension.
+    Some(
+        Path::new(&stem)
+            .file_stem()
+            .map_or(stem.clone(), |stem| stem.to_string_lossy().into_owned()),
+    )
+}
+
+/// A JSON fixture with its witness data parsed, and the rest kept raw so it can be migrated.
+///
+/// The witness data makes up almost all of a fixture, so parsing it straight into its final
+/// type avoids holding a second copy of it as a [Value].
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawFixture {
+    #[serde(default)]
+    witness_data: BTreeMap<B256, Bytes>,
+    #[serde(flatten)]
+    rest: Map<String, Value>,
+}
+
+impl FaultProofFixture {
+    /// Loads a fixture file in the format given by its extension, migrating older versions.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::load_versioned(path)?.0)
+    }
+
+    /// Loads a fixture file like [FaultProofFixture::load], also returning the version of the
+    /// fixture before it was migrated.
+    pub fn load_versioned(path: &Path) -> Result<(Self, u64)> {
+        let format = FixtureFormat::from_path(path)
+            .ok_or_else(|| eyre!("Unknown fixture file extension: {:?}", path))?;
+        let file = File::open(path)?;
+        Self::read(BufReader::new(file), format)
+    }
+
+    /// Reads a fixture in the given format, returning it along with the version of the fixture
+    /// before it was migrated.
+    pub fn read(reader: impl Read, format: FixtureFormat) -> Result<(Self, u64)> {
+        match format.compression {
+            FixtureCompression::None => Self::decode(reader, format.encoding),
+            FixtureCompression::Gzip => {
+                Self::decode(BufReader::new(GzDecoder::new(reader)), format.encoding)
+            }
+            FixtureCompression::Zstd => {
+                Self::decode(BufReader::new(zstd::Decoder::new(reader)?), format.encoding)
+            }
+        }
+    }
+
+    /// Saves the fixture to a file in the format given by its extension.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let format = FixtureFormat::from_path(path)
+            .ok_or_else(|| eyre!("Unknown fixture file extension: {:?}", path))?;
+        let file = File::create(path)?;
+        self.write(BufWriter::new(file), format)
+    }
+
+    /// Writes the fixture in the given format.
+    pub fn write(&self, writer: impl Write, format: FixtureFormat) -> Result<()> {
+        match format.compression {
+            FixtureCompression::None => {
+                let mut writer = writer;
+                self.encode(&mut writer, format.encoding)?;
+                writer.flush()?;
+            }
+            FixtureCompression::Gzip => {
+                let mut encoder = GzEncoder::new(writer, flate2::Compression::default());
+                self.encode(&mut encoder, format.encoding)?;
+                encoder.finish()?.flush()?;
+            }
+            FixtureCompression::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, 0)?;
+                self.encode(&mut encoder, format.encoding)?;
+                encoder.finish()?.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(reader: impl Read, encoding: FixtureEncoding) -> Result<(Self, u64)> {
+        match encoding {
+            FixtureEncoding::Json => {
+                let raw: RawFixture = serde_json::from_reader(reader)?;
+                let mut fixture = Value::Object(raw.rest);
+                let version = migration::migrate(&mut fixture)?;
+                fixture["witnessData"] = Value::Object(Map::new());
+
+                let mut fixture: Self = serde_json::from_value(fixture)?;
+                fixture.witness_data = raw.witness_data;
+                Ok((fixture, version))
+            }
+            FixtureEncoding::Cbor => {
+                let fixture: Self = ciborium::from_reader(reader).map_err(|e| eyre!(e))?;
+                // Binary fixtures were introduced with the versioned format, so there is nothing
+                // to migrate them from.
+                if fixture.version != FIXTURE_VERSION {
+                    return Err(eyre!(
+                        "Unsupported binary fixture version {}, expected {}",
+                        fixture.version,
+                        FIXTURE_VERSION
+                    ));
+                }
+                Ok((fixture, FIXTURE_VERSION))
+            }
+        }
+    }
+
+    fn encode(&self, writer: &mut impl Write, encoding: FixtureEncoding) -> Result<()> {
+        match encoding {
+            FixtureEncoding::Json => serde_json::to_writer_pretty(writer, self)?,
+            FixtureEncoding::Cbor => ciborium::into_writer(self, writer).map_err(|e| eyre!(e))?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{Address, U256};
+
+    fn fixture() -> FaultProofFixture {
+        let mut fixture = FaultProofFixture::default();
+        fixture.inputs.l2_block_number = 1337;
+        fixture.metadata = Some(crate::FixtureMetadata {
+            tags: vec!["test".to_string()],
+            ..Default::default()
+        });
+        for i in 0..16u8 {
+            fixture
+                .witness_data
+                .insert(B256::from([i; 32]), Bytes::from(vec![i; 64]));
+        }
+        fixture
+    }
+
+    #[test]
+    fn test_fixture_format_from_path() {
+        let format = |path: &str| FixtureFormat::from_path(Path::new(path));
+        assert_eq!(
+            format("fixtures/Writer-1000000.json"),
+            Some(FixtureFormat {
+                encoding: FixtureEncoding::Json,
+                compression: FixtureCompression::None,
+            })
+        );
+        assert_eq!(
+            format("Writer.CBOR.zst"),
+            Some(FixtureFormat {
+                encoding: FixtureEncoding::Cbor,
+                compression: FixtureCompression::Zstd,
+            })
+        );
+        assert_eq!(
+            format("Writer.json.gz").map(|f| f.compression),
+            Some(FixtureCompression::Gzip)
+        );
+        assert_eq!(format("Writer.gz"), None);
+        assert_eq!(format("Writer.txt"), None);
+    }
+
+    #[test]
+    fn test_fixture_stem() {
+        let stem = |path: &str| fixture_stem(Path::new(path));
+        assert_eq!(
+            stem("fixtures/Reader-2000000.json").as_deref(),
+            Some("Reader-2000000")
+        );
+        assert_eq!(stem("Reader.json.gz").as_deref(), Some("Reader"));
+        assert_eq!(stem("Reader.cbor.zst").as_deref(), Some("Reader"));
+        assert_eq!(stem("Reader.gz"), None);
+    }
+
+    #[test]
+    fn test_fixture_roundtrip() {
+        let fixture = fixture();
+        for encoding in [FixtureEncoding::Json, FixtureEncoding::Cbor] {
+            for compression in [
+                FixtureCompression::None,
+                FixtureCompression::Gzip,
+                FixtureCompression::Zstd,
+            ] {
+                let format = FixtureFormat {
+                    encoding,
+                    compression,
+                };
+                let mut data = Vec::new();
+                fixture.write(&mut data, format).unwrap();
+                let (decoded, version) = FaultProofFixture::read(data.as_slice(), format).unwrap();
+                assert_eq!(decoded, fixture, "{:?}", format);
+                assert_eq!(version, FIXTURE_VERSION);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unnamed_chain_roundtrip() {
+        let mut rollup_config = superchain_primitives::RollupConfig {
+            block_time: 2,
+            l1_chain_id: 900,
+            l2_chain_id: 901,
+            regolith_time: Some(0),
+            ecotone_time: Some(1_700_000_000),
+            ..Default::default()
+        };
+        rollup_config.genesis.l2_time = 1_600_000_000;
+        rollup_config.genesis.system_config = Some(superchain_primitives::SystemConfig {
+            gas_limit: 30_000_000,
+            scalar: U256::from(684_000),
+            ..Default::default()
+        });
+        let mut genesis = crate::Genesis {
+            timestamp: U256::from(1_600_000_000),
+            gas_limit: U256::from(30_000_000),
+            extra_data: Some(Bytes::from_static(b"genesis")),
+            excess_blob_gas: Some(U256::ZERO),
+            ..Default::default()
+        };
+        genesis.config.chain_id = 901;
+        genesis.alloc.insert(
+            Address::repeat_byte(0x42),
+            crate::AccountState {
+                balance: Some(U256::MAX),
+                code: Some(Bytes::from_static(&[0x60, 0x00, 0xf3])),
+                nonce: None,
+                storage: [(B256::ZERO, B256::repeat_byte(1))].into(),
+            },
+        );
+
+        let mut fixture = fixture();
+        fixture.inputs.chain_definition = crate::ChainDefinition::Unnamed(rollup_config, genesis);
+        for compression in [
+            FixtureCompression::None,
+            FixtureCompression::Gzip,
+            FixtureCompression::Zstd,
+        ] {
+            let format = FixtureFormat {
+                encoding: FixtureEncoding::Cbor,
+                compression,
+            };
+            let mut data = Vec::new();
+            fixture.write(&mut data, format).unwrap();
+            let (decoded, _) = FaultProofFixture::read(data.as_slice(), format).unwrap();
+            assert_eq!(decoded, fixture, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_cbor_stores_raw_bytes() {
+        let fixture = fixture();
+        let size = |encoding| {
+            let mut data = Vec::new();
+            let format = FixtureFormat {
+                encoding,
+                compression: FixtureCompression::None,
+            };
+            fixture.write(&mut data, format).unwrap();
+            data.len()
+        };
+        assert!(size(FixtureEncoding::Cbor) * 2 < size(FixtureEncoding::Json));
+    }
+}
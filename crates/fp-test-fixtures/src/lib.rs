@@ -2,26 +2,254 @@
 
 use std::collections::BTreeMap;
 
-use alloy_primitives::{Address, BlockHash, BlockNumber, Bytes, ChainId, B256, U256};
-use superchain_primitives::RollupConfig;
+use alloy_primitives::{keccak256, Address, BlockHash, BlockNumber, Bytes, ChainId, B256, U256};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use sha2::{Digest, Sha256};
+use superchain_primitives::RollupConfig;
+
+pub mod encoding;
+pub mod migration;
+
+/// The current version of the fixture format.
+pub const FIXTURE_VERSION: u64 = 1;
 
 /// The fault proof fixture is the top-level object that contains
 /// everything needed to run a fault proof test.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct FaultProofFixture {
+    /// The version of the fixture format.
+    #[serde(default)]
+    pub version: u64,
     /// The inputs to the fault proof test.
     pub inputs: FaultProofInputs,
     /// The expected status of the fault proof test.
     pub expected_status: FaultProofStatus,
     /// The witness data for the fault proof test.
     pub witness_data: BTreeMap<B256, Bytes>,
+    /// Optional information about how the fixture was produced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<FixtureMetadata>,
+    /// Optional resource budgets that a run of the fixture must stay within.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budgets: Option<ResourceBudgets>,
+}
+
+impl Default for FaultProofFixture {
+    fn default() -> Self {
+        Self {
+            version: FIXTURE_VERSION,
+            inputs: Default::default(),
+            expected_status: Default::default(),
+            witness_data: Default::default(),
+            metadata: None,
+            budgets: None,
+        }
+    }
+}
+
+impl FaultProofFixture {
+    /// Checks that every keccak256 and sha256 witness entry hashes to its key.
+    ///
+    /// Entries of other key types can't be checked against their key, so only their type byte
+    /// is validated.
+    pub fn validate(&self) -> Result<()> {
+        for (key, preimage) in &self.witness_data {
+            let preimage_key = PreimageKey::try_from(*key)
+                .map_err(|e| eyre!("Invalid witness key {}: {}", key, e))?;
+            let expected = match preimage_key.key_type {
+                PreimageKeyType::Keccak256 => PreimageKey::keccak256(preimage),
+                PreimageKeyType::Sha256 => PreimageKey::sha256(preimage),
+                _ => continue,
+            };
+            if expected != preimage_key {
+                return Err(eyre!(
+                    "Witness entry {} does not match its {:?} preimage, which hashes to {}",
+                    key,
+                    preimage_key.key_type,
+                    B256::from(expected)
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Information about how a fixture was produced.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureMetadata {
+    /// The name and version of the tool that generated the fixture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+    /// The version string reported by the op-program binary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_program_version: Option<String>,
+    /// The sha256 hash of the op-program binary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_program_hash: Option<B256>,
+    /// The creation time of the fixture, in seconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// The name of the script that produced the claimed blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    /// The arguments of the script that produced the claimed blocks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub script_args: Vec<String>,
+    /// The gas used by the claimed L2 blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l2_gas_used: Option<u64>,
+    /// The number of transactions in the claimed L2 blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l2_tx_count: Option<u64>,
+    /// Free-form tags.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// Resource budgets for running a fixture. Unset budgets are not enforced.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceBudgets {
+    /// The maximum number of steps of the fault proof VM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<u64>,
+    /// The maximum memory used by the fault proof VM, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u64>,
+    /// The maximum total size of the requested preimages, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_preimage_size: Option<u64>,
+    /// The maximum runtime, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_runtime_ms: Option<u64>,
+}
+
+impl ResourceBudgets {
+    /// Returns a description of every budget exceeded by the given resource usage.
+    ///
+    /// Usage that was not measured, such as steps outside of a fault proof VM, is not checked.
+    pub fn exceeded(
+        &self,
+        steps: Option<u64>,
+        memory: Option<u64>,
+        preimage_size: Option<u64>,
+        runtime_ms: Option<u64>,
+    ) -> Vec<String> {
+        [
+            ("steps", self.max_steps, steps),
+            ("memory", self.max_memory, memory),
+            ("preimage size", self.max_preimage_size, preimage_size),
+            ("runtime (ms)", self.max_runtime_ms, runtime_ms),
+        ]
+        .into_iter()
+        .filter_map(|(name, budget, used)| match (budget, used) {
+            (Some(budget), Some(used)) if used > budget => Some(format!(
+                "{} {} exceeds the budget of {}",
+                name, used, budget
+            )),
+            _ => None,
+        })
+        .collect()
+    }
+}
+
+/// The type of a preimage key, stored in the first byte of the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum PreimageKeyType {
+    /// A local key, holding the boot information of the program.
+    Local = 1,
+    /// A keccak256 hash of the preimage.
+    Keccak256 = 2,
+    /// A global key with no hashing scheme.
+    GlobalGeneric = 3,
+    /// A sha256 hash of the preimage.
+    Sha256 = 4,
+    /// A blob field element, keyed by the blob commitment and the element's root of unity.
+    Blob = 5,
+    /// A precompile result, keyed by the precompile address and its input.
+    Precompile = 6,
+}
+
+impl TryFrom<u8> for PreimageKeyType {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(PreimageKeyType::Local),
+            2 => Ok(PreimageKeyType::Keccak256),
+            3 => Ok(PreimageKeyType::GlobalGeneric),
+            4 => Ok(PreimageKeyType::Sha256),
+            5 => Ok(PreimageKeyType::Blob),
+            6 => Ok(PreimageKeyType::Precompile),
+            _ => Err(format!("unknown preimage key type {}", value)),
+        }
+    }
+}
+
+/// A preimage key, made up of its type and the remaining 31 bytes of the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PreimageKey {
+    /// The type of the key.
+    pub key_type: PreimageKeyType,
+    /// The key data following the type byte.
+    pub data: [u8; 31],
+}
+
+impl PreimageKey {
+    /// Creates a key of the given type from a 32 byte hash, replacing its first byte.
+    pub fn from_hash(key_type: PreimageKeyType, hash: B256) -> Self {
+        let mut data = [0u8; 31];
+        data.copy_from_slice(&hash[1..]);
+        Self { key_type, data }
+    }
+
+    /// Returns the local key with the given index.
+    pub fn local(index: u64) -> Self {
+        let mut data = [0u8; 31];
+        data[23..].copy_from_slice(&index.to_be_bytes());
+        Self {
+            key_type: PreimageKeyType::Local,
+            data,
+        }
+    }
+
+    /// Returns the keccak256 key of the given preimage.
+    pub fn keccak256(preimage: &[u8]) -> Self {
+        Self::from_hash(PreimageKeyType::Keccak256, keccak256(preimage))
+    }
+
+    /// Returns the sha256 key of the given preimage.
+    pub fn sha256(preimage: &[u8]) -> Self {
+        let hash: [u8; 32] = Sha256::digest(preimage).into();
+        Self::from_hash(PreimageKeyType::Sha256, hash.into())
+    }
+}
+
+impl TryFrom<B256> for PreimageKey {
+    type Error = String;
+
+    fn try_from(key: B256) -> Result<Self, Self::Error> {
+        Ok(Self::from_hash(PreimageKeyType::try_from(key[0])?, key))
+    }
+}
+
+impl From<PreimageKey> for B256 {
+    fn from(key: PreimageKey) -> B256 {
+        let mut bytes = [0u8; 32];
+        bytes[0] = key.key_type as u8;
+        bytes[1..].copy_from_slice(&key.data);
+        B256::from(bytes)
+    }
 }
 
 /// The fault proof inputs are the inputs to the fault proof test.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct FaultProofInputs {
     /// The L1 head block hash.
@@ -39,7 +267,7 @@ pub struct FaultProofInputs {
 }
 
 /// The chain definition, either named or unnamed with a rollup config and genesis.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub enum ChainDefinition {
     /// Named chain definition.
@@ -55,7 +283,7 @@ impl Default for ChainDefinition {
 }
 
 /// The genesis block information.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Genesis {
     /// The chain configuration.
@@ -98,7 +326,7 @@ pub struct Genesis {
 }
 
 /// The chain configuration.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct ChainConfig {
     /// The chain ID.
@@ -199,7 +427,7 @@ pub struct ChainConfig {
 }
 
 /// The optimism configuration.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct OptimismConfig {
     /// The eip1559 elasticity.
@@ -229,7 +457,7 @@ pub struct AccountState {
 }
 
 /// The fault proof status is the result of executing the fault proof program.
-#[derive(Serialize_repr, Deserialize_repr, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize_repr, Deserialize_repr, Debug, Default, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum FaultProofStatus {
     /// The claim is valid.
@@ -245,8 +473,31 @@ pub enum FaultProofStatus {
     Unknown,
 }
 
+impl FaultProofStatus {
+    /// Returns the status for the given program exit code.
+    ///
+    /// The fault proof program exits with `0` when the claim is valid and `1` when it is
+    /// invalid. Any other exit code is treated as a panic.
+    pub fn from_exit_code(exit_code: u8) -> Self {
+        match exit_code {
+            0 => FaultProofStatus::Valid,
+            1 => FaultProofStatus::Invalid,
+            _ => FaultProofStatus::Panic,
+        }
+    }
+
+    /// Returns the status for a fault proof VM with the given exit flag and exit code.
+    pub fn from_vm(exited: bool, exit_code: u8) -> Self {
+        if exited {
+            Self::from_exit_code(exit_code)
+        } else {
+            FaultProofStatus::Unfinished
+        }
+    }
+}
+
 impl TryFrom<u8> for FaultProofStatus {
-        type Error = String;
+    type Error = String;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
@@ -288,6 +539,83 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fault_proof_status_from_vm() {
+        assert_eq!(FaultProofStatus::from_vm(true, 0), FaultProofStatus::Valid);
+        assert_eq!(
+            FaultProofStatus::from_vm(true, 1),
+            FaultProofStatus::Invalid
+        );
+        assert_eq!(FaultProofStatus::from_vm(true, 2), FaultProofStatus::Panic);
+        assert_eq!(FaultProofStatus::from_vm(true, 3), FaultProofStatus::Panic);
+        assert_eq!(
+            FaultProofStatus::from_vm(false, 0),
+            FaultProofStatus::Unfinished
+        );
+    }
+
+    #[test]
+    fn test_preimage_key() {
+        let key = B256::from(PreimageKey::local(4));
+        let mut expected = [0u8; 32];
+        expected[0] = 1;
+        expected[31] = 4;
+        assert_eq!(key, B256::from(expected));
+        assert_eq!(PreimageKey::try_from(key), Ok(PreimageKey::local(4)));
+
+        let key = B256::from(PreimageKey::keccak256(b"hello"));
+        assert_eq!(key[0], 2);
+        assert_eq!(key[1..], keccak256(b"hello")[1..]);
+
+        assert!(PreimageKey::try_from(B256::ZERO).is_err());
+        assert!(PreimageKey::try_from(B256::from([7; 32])).is_err());
+    }
+
+    #[test]
+    fn test_validate_witness_data() {
+        let mut fixture = FaultProofFixture::default();
+        fixture.witness_data.insert(
+            PreimageKey::keccak256(b"keccak").into(),
+            Bytes::from_static(b"keccak"),
+        );
+        fixture.witness_data.insert(
+            PreimageKey::sha256(b"sha256").into(),
+            Bytes::from_static(b"sha256"),
+        );
+        fixture.witness_data.insert(
+            PreimageKey::from_hash(PreimageKeyType::Blob, B256::from([1; 32])).into(),
+            Bytes::from_static(b"blob"),
+        );
+        assert!(fixture.validate().is_ok());
+
+        fixture.witness_data.insert(
+            PreimageKey::sha256(b"sha256").into(),
+            Bytes::from_static(b"corrupted"),
+        );
+        assert!(fixture.validate().is_err());
+    }
+
+    #[test]
+    fn test_resource_budgets_exceeded() {
+        let budgets = ResourceBudgets {
+            max_steps: Some(100),
+            max_memory: Some(1024),
+            max_preimage_size: None,
+            max_runtime_ms: Some(10),
+        };
+
+        assert!(budgets
+            .exceeded(Some(100), Some(1024), Some(u64::MAX), None)
+            .is_empty());
+        assert_eq!(
+            budgets.exceeded(Some(101), None, None, Some(11)),
+            vec![
+                "steps 101 exceeds the budget of 100".to_string(),
+                "runtime (ms) 11 exceeds the budget of 10".to_string(),
+            ]
+        );
+    }
+
     #[test]
     fn test_serialize_fault_proof_inputs() {
         let inputs = FaultProofInputs {
@@ -312,6 +640,7 @@ mod tests {
         witness_data.insert(B256::from([2; 32]), Bytes::from([2; 32]));
 
         let fixture = FaultProofFixture {
+            version: FIXTURE_VERSION,
             inputs: FaultProofInputs {
                 l1_head: B256::from([1; 32]),
                 l2_head: B256::from([2; 32]),
@@ -322,6 +651,17 @@ mod tests {
             },
             expected_status: FaultProofStatus::Valid,
             witness_data,
+            metadata: Some(FixtureMetadata {
+                generator: Some("opfp 0.2.0".to_string()),
+                script: Some("Writer".to_string()),
+                script_args: vec!["1000000".to_string()],
+                l2_gas_used: Some(21000),
+                ..Default::default()
+            }),
+            budgets: Some(ResourceBudgets {
+                max_steps: Some(1_000_000),
+                ..Default::default()
+            }),
         };
 
         let serialized_fixture =
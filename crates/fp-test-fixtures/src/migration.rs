@@ -0,0 +1,168 @@
+//! Migrations that upgrade older fixture layouts to the current [FIXTURE_VERSION].
+//!
+//! Migrations operate on the raw JSON value, as older layouts can't be parsed into the current
+//! types. Each migration upgrades a fixture by exactly one version.
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_json::{json, Map, Value};
+
+use crate::FIXTURE_VERSION;
+
+/// A migration from one fixture version to the next.
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// The chain IDs of the registry networks op-program knows by name.
+pub const REGISTRY_NETWORKS: [(u64, &str); 8] = [
+    (10, "op-mainnet"),
+    (919, "mode-sepolia"),
+    (8453, "base-mainnet"),
+    (34443, "mode-mainnet"),
+    (84532, "base-sepolia"),
+    (7777777, "zora-mainnet"),
+    (11155420, "op-sepolia"),
+    (999999999, "zora-sepolia"),
+];
+
+/// Returns the chain ID of a registry network op-program knows by name.
+pub fn registry_chain_id(name: &str) -> Option<u64> {
+    REGISTRY_NETWORKS
+        .iter()
+        .find(|(_, network)| *network == name)
+        .map(|(chain_id, _)| *chain_id)
+}
+
+/// The migrations, indexed by the version they upgrade from.
+const MIGRATIONS: [Migration; FIXTURE_VERSION as usize] = [migrate_v0];
+
+/// Returns the version of a raw fixture. Fixtures without a version field are version 0.
+pub fn fixture_version(fixture: &Value) -> Result<u64> {
+    match fixture.get("version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| eyre!("Invalid fixture version: {}", version)),
+    }
+}
+
+/// Upgrades a raw fixture to the current [FIXTURE_VERSION], returning the version it had.
+pub fn migrate(fixture: &mut Value) -> Result<u64> {
+    let version = fixture_version(fixture)?;
+    if version > FIXTURE_VERSION {
+        return Err(eyre!(
+            "Fixture version {} is newer than the supported version {}",
+            version,
+            FIXTURE_VERSION
+        ));
+    }
+
+    let object = fixture
+        .as_object_mut()
+        .ok_or_else(|| eyre!("Fixture is not a JSON object"))?;
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(object)
+            .map_err(|e| eyre!("Failed to migrate fixture from version {}: {}", from, e))?;
+        object.insert("version".to_string(), json!(from as u64 + 1));
+    }
+
+    Ok(version)
+}
+
+/// Version 0 identified the chain by its `l2ChainId` input instead of a `chainDefinition`.
+///
+/// The chain ID is replaced with the name op-program knows its registry network by. Chains
+/// outside the registry, such as devnets, can't be migrated, as the fixture lacks their rollup
+/// config and genesis.
+fn migrate_v0(fixture: &mut Map<String, Value>) -> Result<()> {
+    let inputs = fixture
+        .get_mut("inputs")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| eyre!("Missing fixture inputs"))?;
+
+    if let Some(chain_id) = inputs.remove("l2ChainId") {
+        if inputs.contains_key("chainDefinition") {
+            return Ok(());
+        }
+        let id = match &chain_id {
+            Value::Number(id) => id.as_u64(),
+            Value::String(id) => id.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| eyre!("Invalid l2ChainId: {}", chain_id))?;
+        let name = REGISTRY_NETWORKS
+            .iter()
+            .find(|(chain_id, _)| *chain_id == id)
+            .map(|(_, name)| name)
+            .ok_or_else(|| {
+                eyre!(
+                    "Chain ID {} is not a registry network, regenerate the fixture with its rollup config and genesis",
+                    id
+                )
+            })?;
+        inputs.insert("chainDefinition".to_string(), json!({ "named": name }));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChainDefinition, FaultProofFixture};
+
+    #[test]
+    fn test_migrate_v0() {
+        let mut fixture = json!({
+            "inputs": {
+                "l1Head": "0x0101010101010101010101010101010101010101010101010101010101010101",
+                "l2Head": "0x0202020202020202020202020202020202020202020202020202020202020202",
+                "l2Claim": "0x0303030303030303030303030303030303030303030303030303030303030303",
+                "l2OutputRoot": "0x0404040404040404040404040404040404040404040404040404040404040404",
+                "l2BlockNumber": 1337,
+                "l2ChainId": 10
+            },
+            "expectedStatus": 0,
+            "witnessData": {}
+        });
+
+        assert_eq!(migrate(&mut fixture).unwrap(), 0);
+        assert_eq!(fixture_version(&fixture).unwrap(), FIXTURE_VERSION);
+
+        let fixture: FaultProofFixture = serde_json::from_value(fixture).unwrap();
+        assert_eq!(
+            fixture.inputs.chain_definition,
+            ChainDefinition::Named("op-mainnet".to_string())
+        );
+    }
+
+    #[test]
+    fn test_migrate_v0_chain_ids() {
+        let chain_definition = |chain_id: Value| {
+            let mut fixture = json!({ "inputs": { "l2ChainId": chain_id } });
+            migrate(&mut fixture).map(|_| fixture["inputs"]["chainDefinition"].clone())
+        };
+        assert_eq!(
+            chain_definition(json!("8453")).unwrap(),
+            json!({ "named": "base-mainnet" })
+        );
+        assert_eq!(
+            chain_definition(json!(11155420)).unwrap(),
+            json!({ "named": "op-sepolia" })
+        );
+
+        // The devnet's chain ID doesn't name a network op-program can load.
+        let error = chain_definition(json!(901)).unwrap_err().to_string();
+        assert!(
+            error.contains("Chain ID 901 is not a registry network"),
+            "{}",
+            error
+        );
+        assert!(chain_definition(json!("op-mainnet")).is_err());
+    }
+
+    #[test]
+    fn test_migrate_newer_version() {
+        let mut fixture = json!({ "version": FIXTURE_VERSION + 1 });
+        assert!(migrate(&mut fixture).is_err());
+    }
+}